@@ -1,15 +1,56 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 
+#ifdef MULTISAMPLED
 @group(0) @binding(0) var transparency_colour: texture_multisampled_2d<f32>;
 @group(0) @binding(1) var transparency_alpha: texture_multisampled_2d<f32>;
+#else
+@group(0) @binding(0) var transparency_colour: texture_2d<f32>;
+@group(0) @binding(1) var transparency_alpha: texture_2d<f32>;
+#endif
+
+fn linear_to_srgb(colour: vec3<f32>) -> vec3<f32> {
+    let low = colour * 12.92;
+    let high = 1.055 * pow(colour, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, colour <= vec3(0.0031308));
+}
 
 @fragment
 fn fs_main(
     in: FullscreenVertexOutput,
+#ifdef MULTISAMPLED
+#ifndef RESOLVE_SAMPLES
     @builtin(sample_index) sample_index: u32,
+#endif
+#endif
 ) -> @location(0) vec4<f32> {
     let pixel = vec2<u32>(in.position.xy);
+#ifdef RESOLVE_SAMPLES
+    // The target is single-sampled, so average the samples here.
+    let num_samples = textureNumSamples(transparency_colour);
+    var colour = vec4(0.0);
+    var alpha = 0.0;
+    for (var i = 0u; i < num_samples; i += 1u) {
+        colour += textureLoad(transparency_colour, pixel, i32(i));
+        alpha += textureLoad(transparency_alpha, pixel, i32(i)).r;
+    }
+    colour /= f32(num_samples);
+    alpha /= f32(num_samples);
+#else ifdef MULTISAMPLED
     let colour = textureLoad(transparency_colour, pixel, i32(sample_index));
     let alpha = textureLoad(transparency_alpha, pixel, i32(sample_index)).r;
-    return vec4(colour.rgb / max(colour.a, 1e-5), alpha);
+#else
+    let colour = textureLoad(transparency_colour, pixel, 0);
+    let alpha = textureLoad(transparency_alpha, pixel, 0).r;
+#endif
+    // colour holds the weighted sums of premultiplied colour and alpha, alpha the revealage.
+    var average = colour.rgb / max(colour.a, 1e-5);
+#ifdef SRGB_OUTPUT
+    average = linear_to_srgb(average);
+#endif
+#ifdef PREMULTIPLIED
+    let coverage = 1.0 - alpha;
+    return vec4(average * coverage, coverage);
+#else
+    return vec4(average, alpha);
+#endif
 }
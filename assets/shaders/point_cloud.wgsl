@@ -15,11 +15,19 @@ struct VertexOutput {
     @location(0) world_position: vec4<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+    // 0 for the cloud's first point, 1 for its last.
+    @location(3) order: f32,
+    // The point's PointCloudAttribute value, or zero.
+    @location(4) attribute: f32,
+    // Non-zero when the point is flagged in its PointCloudSelection.
+    @location(5) @interpolate(flat) selected: u32,
 }
 
 struct PointCloud {
     world_from_local: mat3x4<f32>,
     previous_world_from_local: mat3x4<f32>,
+    first_point: u32,
+    num_points: u32,
 }
 
 struct PointCloudPoints {
@@ -30,14 +38,32 @@ struct PointCloudIndices {
     indices: array<u32>,
 };
 
+struct PointCloudSettings {
+    global_alpha: f32,
+    near_fade_distance: f32,
+    far_cull_distance: f32,
+    selection_colour: vec4<f32>,
+}
+
 @group(1) @binding(0) var<storage> point_clouds: array<PointCloud>;
 @group(1) @binding(1) var<storage> point_cloud_points: array<vec4<f32>>;
+@group(1) @binding(2) var<uniform> point_cloud_settings: PointCloudSettings;
+@group(1) @binding(3) var<storage> point_cloud_attributes: array<f32>;
+#ifdef POINT_SELECTION
+@group(1) @binding(4) var<storage> point_cloud_selection: array<u32>;
+#endif
+#ifdef SORTED_TRANSPARENCY
+@group(1) @binding(5) var<storage> point_cloud_order: array<u32>;
+#endif
 
 struct PointVertex {
     uv: vec2<f32>,
     world_size: f32,
     world_position: vec3<f32>,
     world_normal: vec3<f32>,
+    order: f32,
+    attribute: f32,
+    selected: u32,
 }
 
 fn point_cloud_uv(index: u32) -> vec2<f32> {
@@ -50,16 +76,36 @@ fn point_cloud_vertex(vertex_index: u32, instance_index: u32) -> PointVertex {
 
     let point_cloud = point_clouds[instance_index];
     let world_from_local = affine3_to_square(point_cloud.world_from_local);
-    let point_local = point_cloud_points[vertex_index / 6];
+#ifdef SORTED_TRANSPARENCY
+    // Each slot draws the point the cloud's back to front order puts there.
+    let point = point_cloud_order[vertex_index / 6];
+#else
+    let point = vertex_index / 6;
+#endif
+    let point_local = point_cloud_points[point];
     let point_world = (world_from_local * vec4(point_local.xyz, 1.0)).xyz;
+
+    // Points at or behind the camera, or past the far cull distance, get a zero-sized quad,
+    // which rasterises nothing. View space looks down -Z.
+    let view_depth = -(view.view_from_world * vec4(point_world, 1.0)).z;
+    let far_distance = point_cloud_settings.far_cull_distance;
+    let culled = view_depth <= 0.0 || (far_distance > 0.0 && view_depth > far_distance);
+    let size = select(point_local.w, 0.0, culled);
+
     out.uv = point_cloud_uv(vertex_index);
-    out.world_size = point_local.w;
+    out.world_size = size;
+    let point_index = point - point_cloud.first_point;
+    out.order = f32(point_index) / f32(max(point_cloud.num_points, 2u) - 1u);
+    out.attribute = point_cloud_attributes[point];
+#ifdef POINT_SELECTION
+    out.selected = point_cloud_selection[point];
+#endif
 
     let right = view.world_from_view[0].xyz;
     let up = view.world_from_view[1].xyz;
 
     let vert_local = vec3(out.uv - 0.5, 0.0);
-    let vert_world = (world_from_local * vec4(vert_local, 0.0)).xyz * point_local.w;
+    let vert_world = (world_from_local * vec4(vert_local, 0.0)).xyz * size;
     out.world_position = point_world + right * vert_world.x + up * vert_world.y;
     out.world_normal = normalize(view.world_position - point_world);
 
@@ -71,9 +117,37 @@ struct FragmentOutput {
     @location(1) alpha: vec4<f32>,
 }
 
-fn calculate_fragment_output(z: f32, colour: vec4<f32>) -> FragmentOutput {
-    let weight = max(min(1.0, max(max(colour.r, colour.g), colour.b) * colour.a), colour.a) *
-        clamp(0.03 / (1e-5 + pow(z / 200.0, 4.0)), 1e-2, 3e3);
+fn point_cloud_near_fade(world_position: vec3<f32>) -> f32 {
+    let fade_distance = point_cloud_settings.near_fade_distance;
+    if fade_distance <= 0.0 {
+        return 1.0;
+    }
+
+    return smoothstep(0.0, fade_distance, length(world_position - view.world_position));
+}
+
+// Depth weight from McGuire and Bavoil, "Weighted Blended Order-Independent Transparency" (eq. 9),
+// which favours nearer fragments so dense distant points don't wash out closer ones.
+fn point_cloud_oit_weight(world_position: vec3<f32>, colour: vec4<f32>) -> f32 {
+    let view_depth = abs((view.view_from_world * vec4(world_position, 1.0)).z);
+    let coverage = max(min(1.0, max(max(colour.r, colour.g), colour.b) * colour.a), colour.a);
+    return coverage * clamp(0.03 / (1e-5 + pow(view_depth / 200.0, 4.0)), 1e-2, 3e3);
+}
+
+fn calculate_fragment_output(in: VertexOutput, in_colour: vec4<f32>) -> FragmentOutput {
+    let world_position = in.world_position.xyz;
+    var base_colour = in_colour;
+    if in.selected != 0u {
+        base_colour = point_cloud_settings.selection_colour;
+    }
+    let alpha = base_colour.a * point_cloud_settings.global_alpha * point_cloud_near_fade(world_position);
+    let colour = vec4(base_colour.rgb, alpha);
+#ifdef SORTED_TRANSPARENCY
+    // Sorted points blend in order, so every fragment counts fully.
+    let weight = 1.0;
+#else
+    let weight = point_cloud_oit_weight(world_position, colour);
+#endif
     var out: FragmentOutput;
     out.colour = vec4(colour.rgb * colour.a, colour.a) * weight;
     out.alpha = vec4(colour.a);
@@ -13,6 +13,9 @@ fn vertex(in: Vertex) -> VertexOutput {
     out.uv = v.uv;
     out.world_position = vec4(v.world_position, 0);
     out.world_normal = v.world_normal;
+    out.order = v.order;
+    out.attribute = v.attribute;
+    out.selected = v.selected;
     out.clip_position = position_world_to_clip(v.world_position);
     return out;
 }
@@ -20,5 +23,5 @@ fn vertex(in: Vertex) -> VertexOutput {
 @fragment
 fn fragment(in: VertexOutput) -> FragmentOutput {
     let colour = vec4(1.0, 1.0, 1.0, 1.0);
-    return calculate_fragment_output(in.clip_position.z, colour);
+    return calculate_fragment_output(in, colour);
 }
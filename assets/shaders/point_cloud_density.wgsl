@@ -0,0 +1,25 @@
+#import bevy_render::color_operations::hsv_to_rgb
+#import "shaders/point_cloud.wgsl"::{
+    VertexOutput, FragmentOutput,
+    calculate_fragment_output,
+}
+
+struct DensityMaterial {
+    log_density_min: f32,
+    log_density_max: f32,
+    hue_sparse: f32,
+    hue_dense: f32,
+    alpha: f32,
+}
+
+@group(2) @binding(0) var<uniform> material: DensityMaterial;
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    // The attribute holds the point's density estimate.
+    let log_density = log(max(in.attribute, 1e-6));
+    let t = saturate((log_density - material.log_density_min) / (material.log_density_max - material.log_density_min));
+    let hue = mix(material.hue_sparse, material.hue_dense, t);
+    let color = vec4(hsv_to_rgb(vec3(hue, 1.0, 1.0)), material.alpha);
+    return calculate_fragment_output(in, color);
+}
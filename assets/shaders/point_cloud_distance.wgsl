@@ -13,6 +13,12 @@ struct DistanceMaterial {
     distance_max: f32,
     hue_min: f32,
     hue_max: f32,
+    reference_point: vec3<f32>,
+    reference_camera: u32,
+    ring_spacing: f32,
+    ring_shade: f32,
+    falloff_mode: u32,
+    falloff_distance: f32,
 }
 
 @group(2) @binding(0) var<uniform> material: DistanceMaterial;
@@ -21,10 +27,26 @@ struct DistanceMaterial {
 
 @fragment
 fn fragment(in: VertexOutput) -> FragmentOutput {
-    let dist = length(in.world_position.xyz - view.world_position);
+    var reference = material.reference_point;
+    if material.reference_camera != 0u {
+        reference = view.world_position;
+    }
+    let dist = length(in.world_position.xyz - reference);
     let frac = smoothstep(material.distance_min, material.distance_max, dist);
     let hue = mix(material.hue_min, material.hue_max, sqrt(frac));
-    let distance_color = vec4(hsv_to_rgb(vec3(hue, 1.0, 1.0)), 0.5);
-    let color = distance_color * textureSample(base_color_texture, base_color_sampler, in.uv);
-    return calculate_fragment_output(in.clip_position.z, color);
+    var value = 1.0;
+    if material.ring_spacing > 0.0 && u32(floor(dist / material.ring_spacing)) % 2u == 1u {
+        value = material.ring_shade;
+    }
+    let distance_color = vec4(hsv_to_rgb(vec3(hue, 1.0, value)), 0.5);
+    var falloff = 1.0;
+    if material.falloff_mode == 1u {
+        falloff = max(1.0 - dist / material.falloff_distance, 0.0);
+    } else if material.falloff_mode == 2u {
+        let scaled = dist / material.falloff_distance;
+        falloff = 1.0 / (1.0 + scaled * scaled);
+    }
+    var color = distance_color * textureSample(base_color_texture, base_color_sampler, in.uv);
+    color.a *= falloff;
+    return calculate_fragment_output(in, color);
 }
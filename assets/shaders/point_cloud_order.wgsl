@@ -0,0 +1,20 @@
+#import bevy_render::color_operations::hsv_to_rgb
+#import "shaders/point_cloud.wgsl"::{
+    VertexOutput, FragmentOutput,
+    calculate_fragment_output,
+}
+
+struct OrderMaterial {
+    hue_first: f32,
+    hue_last: f32,
+    alpha: f32,
+}
+
+@group(2) @binding(0) var<uniform> material: OrderMaterial;
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    let hue = mix(material.hue_first, material.hue_last, in.order);
+    let color = vec4(hsv_to_rgb(vec3(hue, 1.0, 1.0)), material.alpha);
+    return calculate_fragment_output(in, color);
+}
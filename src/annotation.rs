@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+
+use crate::key_bindings::{Action, action_just_pressed};
+
+/// A text label drawn over the view at a point in world space.
+#[derive(Clone, Debug, Component, Reflect)]
+#[reflect(Component)]
+pub struct Annotation {
+    pub position: Vec3,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct AnnotationSettings {
+    pub visible: bool,
+    /// Labels are drawn at this size however far away they are.
+    pub font_size: f32,
+}
+
+impl Default for AnnotationSettings {
+    fn default() -> Self {
+        AnnotationSettings {
+            visible: true,
+            font_size: 16.,
+        }
+    }
+}
+
+/// The UI text node showing an `Annotation`.
+#[derive(Component)]
+pub struct AnnotationLabel {
+    pub annotation: Entity,
+}
+
+pub fn spawn_annotation_labels(
+    mut commands: Commands,
+    settings: Res<AnnotationSettings>,
+    annotations: Query<Entity, Added<Annotation>>,
+) {
+    for entity in &annotations {
+        commands.spawn((
+            Name::new("AnnotationLabel"),
+            TextBundle {
+                text: Text::from_section("", TextStyle {
+                    font_size: settings.font_size,
+                    ..default()
+                }),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                background_color: Color::srgba(0., 0., 0., 0.5).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            AnnotationLabel {
+                annotation: entity,
+            },
+        ));
+    }
+}
+
+/// Moves each label to its annotation's position on screen, hiding it when the point is off
+/// screen, and despawns labels whose annotation has gone.
+pub fn update_annotation_labels(
+    mut commands: Commands,
+    settings: Res<AnnotationSettings>,
+    annotations: Query<&Annotation>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut labels: Query<(Entity, &AnnotationLabel, &mut Text, &mut Style, &mut Visibility)>,
+) {
+    let camera = cameras.iter()
+        .find(|(camera, _)| camera.is_active && matches!(camera.target, RenderTarget::Window(_)));
+
+    for (entity, label, mut text, mut style, mut visibility) in &mut labels {
+        let Ok(annotation) = annotations.get(label.annotation) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        let section = &mut text.sections[0];
+        if section.value != annotation.text {
+            section.value.clone_from(&annotation.text);
+        }
+        if section.style.font_size != settings.font_size {
+            section.style.font_size = settings.font_size;
+        }
+
+        let screen_position = camera
+            .filter(|_| settings.visible)
+            .and_then(|(camera, transform)| camera.world_to_viewport(transform, annotation.position));
+        let new_visibility = match screen_position {
+            Some(screen_position) => {
+                style.left = Val::Px(screen_position.x);
+                style.top = Val::Px(screen_position.y);
+                Visibility::Inherited
+            }
+            None => Visibility::Hidden,
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
+
+pub fn toggle_annotations(
+    mut settings: ResMut<AnnotationSettings>,
+) {
+    settings.visible = !settings.visible;
+}
+
+pub struct AnnotationPlugin;
+
+impl Plugin for AnnotationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Annotation>()
+            .register_type::<AnnotationSettings>()
+            .init_resource::<AnnotationSettings>()
+            .add_systems(Update, (
+                toggle_annotations.run_if(action_just_pressed(Action::ToggleAnnotations)),
+                spawn_annotation_labels,
+                update_annotation_labels,
+            ).chain());
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use bevy::color::palettes::css::ORANGE_RED;
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::physics::PhysicsWorld;
+use crate::point_cloud::{PointCloud, PointCloudSelection};
+use crate::scanner::MAX_SCAN_DISTANCE;
+
+/// Removes scanned points within a sphere around the scene point under the cursor.
+///
+/// While `key` is held a preview of the sphere is drawn, the points inside it are selected, and
+/// pressing `button` erases.
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Eraser {
+    pub radius: f32,
+    pub key: KeyCode,
+    pub button: MouseButton,
+}
+
+impl Default for Eraser {
+    fn default() -> Self {
+        Eraser {
+            radius: 0.25,
+            key: KeyCode::KeyV,
+            button: MouseButton::Middle,
+        }
+    }
+}
+
+fn cursor_ray(window: &Window, camera: &Camera, camera_transform: &GlobalTransform) -> Option<Ray3d> {
+    // When the cursor is grabbed, aim through the middle of the view instead.
+    let cursor = if window.cursor.visible {
+        window.cursor_position()?
+    } else {
+        camera.logical_viewport_size()? * 0.5
+    };
+    camera.viewport_to_world(camera_transform, cursor)
+}
+
+/// Removes the `PointCloudSelection` from clouds the eraser selected, leaving any others alone.
+fn clear_eraser_selection(commands: &mut Commands, selected_clouds: &mut EntityHashSet) {
+    for entity in selected_clouds.drain() {
+        if let Some(mut point_cloud) = commands.get_entity(entity) {
+            point_cloud.remove::<PointCloudSelection>();
+        }
+    }
+}
+
+pub fn update_eraser(
+    mut commands: Commands,
+    eraser: Res<Eraser>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    physics_world: Res<PhysicsWorld>,
+    mut gizmos: Gizmos,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut point_clouds: Query<(Entity, &mut PointCloud, &GlobalTransform, Option<&PointCloudSelection>)>,
+    mut selected_clouds: Local<EntityHashSet>,
+) {
+    if !key_input.pressed(eraser.key) {
+        clear_eraser_selection(&mut commands, &mut selected_clouds);
+        return;
+    }
+
+    let center = windows.get_single().ok()
+        .and_then(|window| cameras.iter()
+            .filter(|(camera, _)| camera.is_active)
+            .find_map(|(camera, transform)| cursor_ray(window, camera, transform)))
+        .and_then(|ray| physics_world.ray_cast(ray.origin, ray.get_point(MAX_SCAN_DISTANCE)));
+
+    let Some(center) = center else {
+        clear_eraser_selection(&mut commands, &mut selected_clouds);
+        return;
+    };
+
+    gizmos.sphere(center, Quat::IDENTITY, eraser.radius, ORANGE_RED);
+
+    let erase = mouse_input.just_pressed(eraser.button);
+    let radius_squared = eraser.radius * eraser.radius;
+    for (entity, mut point_cloud, transform, selection) in &mut point_clouds {
+        let local_center = transform.affine().inverse().transform_point3(center);
+        let within = point_cloud.points_within(local_center, eraser.radius).collect::<Vec<_>>();
+        if within.is_empty() {
+            if selected_clouds.remove(&entity) {
+                commands.entity(entity).remove::<PointCloudSelection>();
+            }
+            continue;
+        }
+
+        if erase {
+            Arc::make_mut(&mut point_cloud.points)
+                .retain(|point| point.truncate().distance_squared(local_center) > radius_squared);
+            continue;
+        }
+
+        // Highlight the points that would be erased, replacing the flags only when they change.
+        let unchanged = selection.is_some_and(|selection| selection.selected.iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(index, _)| index)
+            .eq(within.iter().copied()));
+        if !unchanged {
+            let mut selected = vec![false; point_cloud.points.len()];
+            for index in within {
+                selected[index] = true;
+            }
+            commands.entity(entity).insert(PointCloudSelection { selected: Arc::new(selected) });
+        }
+        selected_clouds.insert(entity);
+    }
+}
+
+pub struct EraserPlugin;
+
+impl Plugin for EraserPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Eraser>()
+            .init_resource::<Eraser>()
+            .add_systems(Update, update_eraser);
+    }
+}
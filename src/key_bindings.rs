@@ -0,0 +1,198 @@
+use std::fmt::Write;
+
+use bevy::prelude::*;
+
+use crate::eraser::Eraser;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum Action {
+    ToggleHelp,
+    ResetZoom,
+    ToggleCursorGrab,
+    ToggleLights,
+    ClearScan,
+    ToggleBoost,
+    ToggleFullscreen,
+    CycleAntiAliasing,
+    TogglePointCloudFade,
+    ToggleDensityHeatmap,
+    ToggleScannerFreeze,
+    ToggleBufferBar,
+    ToggleRangeRings,
+    SnapshotScan,
+    ToggleBloom,
+    ToggleAxes,
+    FitDistanceRange,
+    RecordCameraKeyframe,
+    PlayCameraPath,
+    ToggleAnnotations,
+    /// Switch to the scene at this index in `Scenes`.
+    SelectScene(u8),
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::ToggleHelp => "Show or hide this help",
+            Action::ResetZoom => "Reset camera zoom",
+            Action::ToggleCursorGrab => "Grab or release the cursor",
+            Action::ToggleLights => "Toggle lights",
+            Action::ClearScan => "Clear the scan",
+            Action::ToggleBoost => "Toggle scanner boost",
+            Action::ToggleFullscreen => "Toggle fullscreen",
+            Action::CycleAntiAliasing => "Cycle anti-aliasing (MSAA, FXAA, off)",
+            Action::TogglePointCloudFade => "Fade point clouds",
+            Action::ToggleDensityHeatmap => "Colour the scan by point density",
+            Action::ToggleScannerFreeze => "Freeze scanners",
+            Action::ToggleBufferBar => "Show point buffer usage",
+            Action::ToggleRangeRings => "Toggle range rings",
+            Action::SnapshotScan => "Snapshot the scan",
+            Action::ToggleBloom => "Toggle bloom",
+            Action::ToggleAxes => "Toggle axes",
+            Action::FitDistanceRange => "Fit colours to the scan",
+            Action::RecordCameraKeyframe => "Record a camera path keyframe",
+            Action::PlayCameraPath => "Play or stop the camera path",
+            Action::ToggleAnnotations => "Show or hide annotations",
+            Action::SelectScene(_) => "Switch scene",
+        }
+    }
+}
+
+/// The keys bound to each `Action`. Rebind by editing this resource.
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct KeyBindings {
+    pub bindings: Vec<(Action, KeyCode)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: vec![
+                (Action::ToggleHelp, KeyCode::F1),
+                (Action::ResetZoom, KeyCode::KeyC),
+                (Action::ToggleCursorGrab, KeyCode::KeyG),
+                (Action::ToggleLights, KeyCode::KeyL),
+                (Action::ClearScan, KeyCode::KeyR),
+                (Action::ToggleBoost, KeyCode::KeyB),
+                (Action::ToggleFullscreen, KeyCode::F11),
+                (Action::CycleAntiAliasing, KeyCode::KeyM),
+                (Action::TogglePointCloudFade, KeyCode::KeyF),
+                (Action::ToggleDensityHeatmap, KeyCode::KeyY),
+                (Action::ToggleScannerFreeze, KeyCode::KeyP),
+                (Action::ToggleBufferBar, KeyCode::F3),
+                (Action::ToggleRangeRings, KeyCode::KeyN),
+                (Action::SnapshotScan, KeyCode::KeyK),
+                (Action::ToggleBloom, KeyCode::KeyH),
+                (Action::ToggleAxes, KeyCode::KeyO),
+                (Action::FitDistanceRange, KeyCode::KeyT),
+                (Action::RecordCameraKeyframe, KeyCode::KeyJ),
+                (Action::PlayCameraPath, KeyCode::KeyU),
+                (Action::ToggleAnnotations, KeyCode::KeyI),
+                (Action::SelectScene(0), KeyCode::Digit1),
+                (Action::SelectScene(1), KeyCode::Digit2),
+                (Action::SelectScene(2), KeyCode::Digit3),
+                (Action::SelectScene(3), KeyCode::Digit4),
+                (Action::SelectScene(4), KeyCode::Digit5),
+                (Action::SelectScene(5), KeyCode::Digit6),
+                (Action::SelectScene(6), KeyCode::Digit7),
+                (Action::SelectScene(7), KeyCode::Digit8),
+                (Action::SelectScene(8), KeyCode::Digit9),
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn just_pressed(&self, key_input: &ButtonInput<KeyCode>, action: Action) -> bool {
+        self.bindings.iter()
+            .any(|(bound_action, key_code)| *bound_action == action && key_input.just_pressed(*key_code))
+    }
+}
+
+/// Run condition that is true on the frame a key bound to `action` is pressed.
+pub fn action_just_pressed(action: Action) -> impl FnMut(Res<ButtonInput<KeyCode>>, Res<KeyBindings>) -> bool + Clone {
+    move |key_input: Res<ButtonInput<KeyCode>>, key_bindings: Res<KeyBindings>| {
+        key_bindings.just_pressed(&key_input, action)
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct HelpText;
+
+pub fn spawn_help_text(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        Name::new("HelpText"),
+        TextBundle {
+            text: Text {
+                sections: vec![TextSection::new("", TextStyle::default())],
+                ..default()
+            },
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(12.),
+                left: Val::Px(12.),
+                ..default()
+            },
+            background_color: Color::srgba(0., 0., 0., 0.75).into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        HelpText,
+    ));
+}
+
+pub fn toggle_help(
+    mut help_texts: Query<&mut Visibility, With<HelpText>>,
+) {
+    for mut visibility in &mut help_texts {
+        let new_visibility = match *visibility {
+            Visibility::Inherited | Visibility::Visible => Visibility::Hidden,
+            Visibility::Hidden => Visibility::Inherited,
+        };
+        *visibility = new_visibility;
+    }
+}
+
+pub fn update_help_text(
+    key_bindings: Res<KeyBindings>,
+    eraser: Res<Eraser>,
+    mut help_texts: Query<&mut Text, With<HelpText>>,
+    added: Query<(), Added<HelpText>>,
+) {
+    if !key_bindings.is_changed() && !eraser.is_changed() && added.is_empty() {
+        return;
+    }
+
+    for mut text in &mut help_texts {
+        let section = &mut text.sections[0];
+        section.value.clear();
+        for (action, key_code) in &key_bindings.bindings {
+            match action {
+                Action::SelectScene(index) => writeln!(&mut section.value, "{:?}: {} {}", key_code, action.description(), index + 1),
+                _ => writeln!(&mut section.value, "{:?}: {}", key_code, action.description()),
+            }.unwrap();
+        }
+        writeln!(&mut section.value, "WASD, Q/E: move, Shift: faster, Z/X: zoom").unwrap();
+        writeln!(&mut section.value, "Left mouse: scan, right mouse: burst, wheel: scan size").unwrap();
+        write!(&mut section.value, "{:?} + {:?}: erase points", eraser.key, eraser.button).unwrap();
+    }
+}
+
+pub struct KeyBindingsPlugin;
+
+impl Plugin for KeyBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<KeyBindings>()
+            .init_resource::<KeyBindings>()
+            .add_systems(Startup, spawn_help_text)
+            .add_systems(Update, (
+                toggle_help.run_if(action_just_pressed(Action::ToggleHelp)),
+                update_help_text,
+            ));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod transparency;
+pub mod point_cloud;
+pub mod scanner;
+pub mod physics;
+pub mod eraser;
+pub mod annotation;
+pub mod key_bindings;
+pub mod sandbox;
@@ -1,31 +1,164 @@
+use bevy::ecs::entity::EntityHashMap;
+use bevy::math::Affine3A;
 use bevy::prelude::*;
-use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::scene::SceneInstance;
+use bevy::transform::TransformSystem;
+use bevy::tasks::{AsyncComputeTaskPool, block_on, Task};
+use bevy::tasks::futures_lite::future;
 use parry3d::math::{Point, Vector};
 use parry3d::query::Ray;
 use parry3d::shape::{SharedShape, TriMesh};
 
-#[derive(Resource)]
-pub struct PhysicsWorld(Option<SharedShape>);
+struct DynamicShape {
+    shape: SharedShape,
+    world_from_local: Affine3A,
+    transparent: bool,
+}
+
+#[derive(Default, Resource)]
+pub struct PhysicsWorld {
+    solid: Option<SharedShape>,
+    transparent: Option<SharedShape>,
+    dynamic: EntityHashMap<DynamicShape>,
+}
 
-impl Default for PhysicsWorld {
-    fn default() -> Self {
-        PhysicsWorld(None)
+fn build_shape(vertices: Vec<Vec3>, indices: Vec<[u32; 3]>) -> Option<SharedShape> {
+    if indices.is_empty() {
+        return None;
     }
+
+    let vertices = vertices.into_iter()
+        .map(|vertex| Point::from(vertex.to_array()))
+        .collect();
+    Some(SharedShape::new(TriMesh::new(vertices, indices)))
+}
+
+fn cast_shape(shape: Option<&SharedShape>, start: Vec3, end: Vec3) -> Option<Vec3> {
+    let shape = shape?;
+    let dir = end - start;
+    if dir == Vec3::ZERO {
+        return None;
+    }
+
+    let ray = Ray {
+        origin: Point::from(start.to_array()),
+        dir: Vector::from(dir.to_array()),
+    };
+    let t = shape.cast_local_ray(&ray, 1.0, true)?;
+    Some(start + t * dir)
+}
+
+fn cast_dynamic_shape(dynamic: &DynamicShape, start: Vec3, end: Vec3) -> Option<Vec3> {
+    // Casting the segment in local space handles scale, and keeps the hit parameter unchanged.
+    let local_from_world = dynamic.world_from_local.inverse();
+    let hit = cast_shape(
+        Some(&dynamic.shape),
+        local_from_world.transform_point3(start),
+        local_from_world.transform_point3(end),
+    )?;
+    Some(dynamic.world_from_local.transform_point3(hit))
+}
+
+fn closest_hit<T>(start: Vec3, hits: impl Iterator<Item = (Vec3, T)>) -> Option<(Vec3, T)> {
+    hits.min_by(|(a, _), (b, _)| a.distance_squared(start).total_cmp(&b.distance_squared(start)))
 }
 
 impl PhysicsWorld {
+    pub fn from_triangles(vertices: Vec<Vec3>, indices: Vec<[u32; 3]>) -> PhysicsWorld {
+        PhysicsWorld {
+            solid: build_shape(vertices, indices),
+            transparent: None,
+            dynamic: EntityHashMap::default(),
+        }
+    }
+
+    pub fn with_transparent_triangles(mut self, vertices: Vec<Vec3>, indices: Vec<[u32; 3]>) -> PhysicsWorld {
+        self.transparent = build_shape(vertices, indices);
+        self
+    }
+
+    /// Casts against solid geometry only; colliders tagged with `TransparentToScan` are ignored.
     pub fn ray_cast(&self, start: Vec3, end: Vec3) -> Option<Vec3> {
-        let Some(world) = self.0.as_ref() else {
-            return None;
-        };
-        let dir = end - start;
-        let ray = Ray {
-            origin: Point::from(start.to_array()),
-            dir: Vector::from(dir.to_array()),
+        self.cast(self.solid.as_ref(), false, start, end)
+            .map(|(hit, _)| hit)
+    }
+
+    /// Casts against transparent geometry only.
+    pub fn ray_cast_transparent(&self, start: Vec3, end: Vec3) -> Option<Vec3> {
+        self.cast(self.transparent.as_ref(), true, start, end)
+            .map(|(hit, _)| hit)
+    }
+
+    /// Like `ray_cast`, but also returns the `DynamicCollider` entity hit. Hits on the baked
+    /// geometry return `None` for the entity.
+    pub fn ray_cast_entity(&self, start: Vec3, end: Vec3) -> Option<(Vec3, Option<Entity>)> {
+        self.cast(self.solid.as_ref(), false, start, end)
+    }
+
+    fn cast(&self, baked: Option<&SharedShape>, transparent: bool, start: Vec3, end: Vec3) -> Option<(Vec3, Option<Entity>)> {
+        let baked_hit = cast_shape(baked, start, end).map(|hit| (hit, None));
+        let dynamic_hits = self.dynamic.iter()
+            .filter(|(_, dynamic)| dynamic.transparent == transparent)
+            .filter_map(|(entity, dynamic)| Some((cast_dynamic_shape(dynamic, start, end)?, Some(*entity))));
+        closest_hit(start, baked_hit.into_iter().chain(dynamic_hits))
+    }
+
+    /// The number of vertices in the baked static geometry.
+    pub fn vertex_count(&self) -> usize {
+        [&self.solid, &self.transparent].into_iter()
+            .flatten()
+            .filter_map(|shape| shape.as_trimesh())
+            .map(|trimesh| trimesh.vertices().len())
+            .sum()
+    }
+
+    /// Replaces the baked static geometry, keeping any dynamic colliders.
+    pub fn replace_static(&mut self, other: PhysicsWorld) {
+        self.solid = other.solid;
+        self.transparent = other.transparent;
+    }
+}
+
+/// Marks a mesh whose triangles should let scanner rays pass through, such as a window.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct TransparentToScan;
+
+/// Marks a mesh that moves, such as an animated part of a scene. It is left out of the baked
+/// physics world and given its own collider, which follows its `GlobalTransform` every frame.
+///
+/// Add this before the scene's physics world is baked.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct DynamicCollider;
+
+#[derive(Default)]
+struct Triangles {
+    vertices: Vec<Vec3>,
+    indices: Vec<[u32; 3]>,
+}
+
+impl Triangles {
+    fn push(&mut self, triangle: [Vec3; 3]) {
+        let first_vertex = self.vertices.len() as u32;
+        self.vertices.extend(triangle);
+        self.indices.push([first_vertex, first_vertex + 1, first_vertex + 2]);
+    }
+
+    fn push_mesh(&mut self, positions: &[[f32; 3]], indices: Option<&Indices>, transform: impl Fn(Vec3) -> Vec3) {
+        let indices = match indices {
+            Some(indices) => indices.iter().collect::<Vec<_>>(),
+            None => (0..positions.len()).collect(),
         };
-        let t = world.cast_local_ray(&ray, 1.0, true)?;
-        Some(start + t * dir)
+        for chunk in indices.chunks_exact(3) {
+            let Some(triangle) = chunk.iter()
+                .map(|index| positions.get(*index).map(|p| transform((*p).into())))
+                .collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            self.push([triangle[0], triangle[1], triangle[2]]);
+        }
     }
 }
 
@@ -35,52 +168,132 @@ pub struct PhysicsScene;
 #[derive(Component)]
 pub struct LoadedPhysicsScene;
 
+#[derive(Component)]
+pub struct PhysicsWorldTask(Task<PhysicsWorld>);
+
+/// Sent when a baked physics world has been installed and is ready to scan.
+#[derive(Clone, Copy, Debug, Event)]
+pub struct PhysicsWorldBuilt {
+    pub scene: Entity,
+    pub vertex_count: usize,
+}
+
+struct ColliderSource {
+    transform: GlobalTransform,
+    positions: Vec<[f32; 3]>,
+    indices: Option<Indices>,
+    transparent: bool,
+}
+
+fn bake_physics_world(sources: Vec<ColliderSource>) -> PhysicsWorld {
+    let mut solid = Triangles::default();
+    let mut transparent = Triangles::default();
+    for source in sources {
+        let triangles = if source.transparent {
+            &mut transparent
+        } else {
+            &mut solid
+        };
+
+        triangles.push_mesh(&source.positions, source.indices.as_ref(), |p| source.transform.transform_point(p));
+    }
+
+    info!("Loaded {} solid and {} transparent vertices.", solid.vertices.len(), transparent.vertices.len());
+    PhysicsWorld::from_triangles(solid.vertices, solid.indices)
+        .with_transparent_triangles(transparent.vertices, transparent.indices)
+}
+
 pub fn build_physics_world(
     mut commands: Commands,
-    mut physics_world: ResMut<PhysicsWorld>,
     meshes: Res<Assets<Mesh>>,
     scenes: Query<Entity, (With<PhysicsScene>, With<SceneInstance>, Without<LoadedPhysicsScene>)>,
-    colliders: Query<(&GlobalTransform, &Handle<Mesh>)>,
+    colliders: Query<(&GlobalTransform, &Handle<Mesh>, Has<TransparentToScan>), Without<DynamicCollider>>,
 ) {
     for entity in &scenes {
         if colliders.is_empty() {
             continue;
         }
 
-        commands.entity(entity).insert(LoadedPhysicsScene);
-
         info!("Loading physics world...");
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-        for (transform, mesh_handle) in &colliders {
-            let Some(mesh) = meshes.get(mesh_handle) else {
-                continue;
-            };
+        let sources = colliders.iter()
+            .filter_map(|(transform, mesh_handle, transparent)| {
+                let mesh = meshes.get(mesh_handle)?;
+                let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+                    return None;
+                };
 
-            let mut mesh = mesh.clone();
-            mesh.duplicate_vertices();
+                Some(ColliderSource {
+                    transform: *transform,
+                    positions: positions.clone(),
+                    indices: mesh.indices().cloned(),
+                    transparent,
+                })
+            })
+            .collect::<Vec<_>>();
 
-            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
-                continue;
-            };
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            bake_physics_world(sources)
+        });
+        commands.entity(entity).insert((LoadedPhysicsScene, PhysicsWorldTask(task)));
+    }
+}
 
-            for chunk in positions.chunks_exact(3) {
-                let first_vertex = vertices.len() as u32;
-                let a = Point::from(transform.transform_point(chunk[0].into()).to_array());
-                let b = Point::from(transform.transform_point(chunk[1].into()).to_array());
-                let c = Point::from(transform.transform_point(chunk[2].into()).to_array());
-                vertices.extend([a, b, c]);
-                indices.push([first_vertex, first_vertex + 1, first_vertex + 2]);
-            }
+pub fn finish_physics_world(
+    mut commands: Commands,
+    mut physics_world: ResMut<PhysicsWorld>,
+    mut tasks: Query<(Entity, &mut PhysicsWorldTask)>,
+    mut built_events: EventWriter<PhysicsWorldBuilt>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let Some(new_world) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+
+        commands.entity(entity).remove::<PhysicsWorldTask>();
+        physics_world.replace_static(new_world);
+        built_events.send(PhysicsWorldBuilt {
+            scene: entity,
+            vertex_count: physics_world.vertex_count(),
+        });
+    }
+}
+
+pub fn update_dynamic_colliders(
+    meshes: Res<Assets<Mesh>>,
+    mut physics_world: ResMut<PhysicsWorld>,
+    colliders: Query<(Entity, &GlobalTransform, &Handle<Mesh>, Has<TransparentToScan>), With<DynamicCollider>>,
+    mut removed: RemovedComponents<DynamicCollider>,
+) {
+    for entity in removed.read() {
+        physics_world.dynamic.remove(&entity);
+    }
+
+    for (entity, transform, mesh_handle, transparent) in &colliders {
+        let world_from_local = transform.affine();
+        if let Some(dynamic) = physics_world.dynamic.get_mut(&entity) {
+            dynamic.world_from_local = world_from_local;
+            dynamic.transparent = transparent;
+            continue;
         }
 
-        info!("Loaded {} vertices.", vertices.len());
-        let new_world = if vertices.is_empty() {
-            None
-        } else {
-            Some(SharedShape::new(TriMesh::new(vertices, indices)))
+        let Some(mesh) = meshes.get(mesh_handle) else {
+            continue;
+        };
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            continue;
         };
-        physics_world.0 = new_world;
+
+        let mut triangles = Triangles::default();
+        triangles.push_mesh(positions, mesh.indices(), |p| p);
+
+        let Some(shape) = build_shape(triangles.vertices, triangles.indices) else {
+            continue;
+        };
+        physics_world.dynamic.insert(entity, DynamicShape {
+            shape,
+            world_from_local,
+            transparent,
+        });
     }
 }
 
@@ -89,9 +302,83 @@ pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app
+            .register_type::<TransparentToScan>()
+            .register_type::<DynamicCollider>()
             .init_resource::<PhysicsWorld>()
+            .add_event::<PhysicsWorldBuilt>()
             .add_systems(Update, (
                 build_physics_world,
-            ));
+                finish_physics_world,
+            ))
+            .add_systems(PostUpdate, update_dynamic_colliders.after(TransformSystem::TransformPropagate));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+
+    use super::*;
+
+    /// A square from -1 to 1 on X and Y, at z = 0.
+    fn quad() -> PhysicsWorld {
+        PhysicsWorld::from_triangles(
+            vec![vec3(-1., -1., 0.), vec3(1., -1., 0.), vec3(1., 1., 0.), vec3(-1., 1., 0.)],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    /// A cube from -1 to 1 on each axis.
+    fn cube() -> PhysicsWorld {
+        let vertices = (0..8)
+            .map(|i| vec3(
+                if i & 1 == 0 { -1. } else { 1. },
+                if i & 2 == 0 { -1. } else { 1. },
+                if i & 4 == 0 { -1. } else { 1. },
+            ))
+            .collect();
+        let indices = vec![
+            [0, 2, 1], [1, 2, 3],
+            [4, 5, 6], [5, 7, 6],
+            [0, 1, 4], [1, 5, 4],
+            [2, 6, 3], [3, 6, 7],
+            [0, 4, 2], [2, 4, 6],
+            [1, 3, 5], [3, 7, 5],
+        ];
+        PhysicsWorld::from_triangles(vertices, indices)
+    }
+
+    #[test]
+    fn ray_cast_returns_the_nearest_hit_point() {
+        let hit = cube().ray_cast(vec3(0.25, 0.5, 5.), vec3(0.25, 0.5, -5.))
+            .expect("ray through the cube should hit it");
+        assert!(hit.distance(vec3(0.25, 0.5, 1.)) < 1e-5, "hit at {hit}");
+    }
+
+    #[test]
+    fn ray_cast_misses_geometry_off_the_ray() {
+        assert_eq!(cube().ray_cast(vec3(3., 3., 5.), vec3(3., 3., -5.)), None);
+        // The segment stops short of the quad.
+        assert_eq!(quad().ray_cast(vec3(0., 0., 5.), vec3(0., 0., 1.)), None);
+    }
+
+    #[test]
+    fn ray_cast_grazing_the_edge() {
+        let world = quad();
+        let inside = world.ray_cast(vec3(0.999, 0., 1.), vec3(0.999, 0., -1.))
+            .expect("ray just inside the edge should hit");
+        assert!(inside.distance(vec3(0.999, 0., 0.)) < 1e-5, "hit at {inside}");
+        assert_eq!(world.ray_cast(vec3(1.001, 0., 1.), vec3(1.001, 0., -1.)), None);
+    }
+
+    #[test]
+    fn ray_cast_parallel_to_a_face_misses() {
+        assert_eq!(quad().ray_cast(vec3(-5., 0., 0.5), vec3(5., 0., 0.5)), None);
+        assert_eq!(cube().ray_cast(vec3(-5., 1.5, 0.), vec3(5., 1.5, 0.)), None);
+    }
+
+    #[test]
+    fn ray_cast_ignores_zero_length_segments() {
+        assert_eq!(quad().ray_cast(Vec3::ZERO, Vec3::ZERO), None);
     }
 }
@@ -0,0 +1,252 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::color::palettes::css::{DARK_SLATE_GRAY, LIME, YELLOW};
+use bevy::prelude::*;
+use bevy::render::{Render, RenderApp, RenderSet};
+
+use crate::point_cloud::{PointCloud, PointCloudBuffers, PointCloudInstances};
+
+#[derive(Clone, Debug, Default)]
+pub struct PointCloudBufferStats {
+    pub capacity: u32,
+    pub total_free: u32,
+    pub largest_free: u32,
+    /// Allocated `(offset, size)` ranges of the point buffer, sorted and with neighbours merged.
+    pub used_regions: Vec<(u32, u32)>,
+}
+
+/// Point buffer statistics, written by the render world and read by the main world.
+#[derive(Clone, Default, Resource)]
+pub struct SharedPointCloudBufferStats(pub Arc<Mutex<PointCloudBufferStats>>);
+
+impl SharedPointCloudBufferStats {
+    pub fn get(&self) -> PointCloudBufferStats {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+pub fn update_point_cloud_buffer_stats(
+    point_cloud_buffers: Res<PointCloudBuffers>,
+    point_cloud_instances: Res<PointCloudInstances>,
+    shared_stats: Res<SharedPointCloudBufferStats>,
+) {
+    // Chunks are laid out end to end.
+    let chunks = &point_cloud_buffers.allocator.chunks;
+    let chunk_capacity = point_cloud_buffers.chunk_capacity;
+    let mut allocations = point_cloud_instances.values()
+        .filter_map(|instance| instance.allocation)
+        .map(|allocation| {
+            let allocator = &chunks[allocation.chunk as usize];
+            (
+                allocation.chunk * chunk_capacity + allocation.allocation.offset,
+                allocator.allocation_size(allocation.allocation),
+            )
+        })
+        .collect::<Vec<_>>();
+    allocations.sort_unstable();
+
+    let mut used_regions: Vec<(u32, u32)> = Vec::with_capacity(allocations.len());
+    for (offset, size) in allocations {
+        match used_regions.last_mut() {
+            Some((last_offset, last_size)) if *last_offset + *last_size >= offset => {
+                *last_size = (*last_size).max(offset + size - *last_offset);
+            }
+            _ => used_regions.push((offset, size)),
+        }
+    }
+
+    let (total_free, largest_free) = chunks.iter()
+        .map(|allocator| allocator.storage_report())
+        .fold((0, 0), |(total, largest), report| {
+            (total + report.total_free_space, largest.max(report.largest_free_region))
+        });
+    *shared_stats.0.lock().unwrap() = PointCloudBufferStats {
+        capacity: point_cloud_buffers.capacity,
+        total_free,
+        largest_free,
+        used_regions,
+    };
+}
+
+/// A bar across the top of the screen showing which parts of the point buffer are allocated.
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PointCloudBufferBar {
+    /// The capacity and regions the bar's children currently show.
+    #[reflect(ignore)]
+    pub drawn: Option<(u32, Vec<(u32, u32)>)>,
+}
+
+pub fn spawn_point_cloud_buffer_bar(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        Name::new("PointCloudBufferBar"),
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::ZERO,
+                left: Val::ZERO,
+                width: Val::Percent(100.),
+                height: Val::Px(8.),
+                ..default()
+            },
+            background_color: Color::from(DARK_SLATE_GRAY).into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        PointCloudBufferBar::default(),
+    ));
+}
+
+pub fn update_point_cloud_buffer_bar(
+    mut commands: Commands,
+    shared_stats: Res<SharedPointCloudBufferStats>,
+    mut bars: Query<(Entity, &InheritedVisibility, &mut PointCloudBufferBar)>,
+) {
+    let stats = shared_stats.get();
+    if stats.capacity == 0 {
+        return;
+    }
+
+    for (entity, visibility, mut buffer_bar) in &mut bars {
+        if !visibility.get() {
+            continue;
+        }
+
+        // Only rebuild the children when the allocations have changed.
+        if buffer_bar.drawn.as_ref()
+            .is_some_and(|(capacity, regions)| *capacity == stats.capacity && *regions == stats.used_regions) {
+            continue;
+        }
+        buffer_bar.drawn = Some((stats.capacity, stats.used_regions.clone()));
+
+        let mut bar = commands.entity(entity);
+        bar.despawn_descendants();
+        bar.with_children(|children| {
+            for (offset, size) in &stats.used_regions {
+                children.spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        top: Val::ZERO,
+                        bottom: Val::ZERO,
+                        left: Val::Percent(*offset as f32 * 100. / stats.capacity as f32),
+                        width: Val::Percent(*size as f32 * 100. / stats.capacity as f32),
+                        ..default()
+                    },
+                    background_color: Color::from(LIME).into(),
+                    ..default()
+                });
+            }
+        });
+    }
+}
+
+/// Draws the oriented bounding box of a cloud with a gizmo.
+///
+/// The box is computed when the gizmo is added and recomputed when the cloud's points change.
+#[derive(Clone, Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PointCloudObbGizmo {
+    #[reflect(ignore)]
+    pub obb: Option<(Transform, Vec3)>,
+}
+
+pub fn update_point_cloud_obb_gizmos(
+    mut point_clouds: Query<
+        (&PointCloud, &mut PointCloudObbGizmo),
+        Or<(Changed<PointCloud>, Added<PointCloudObbGizmo>)>,
+    >,
+) {
+    for (point_cloud, mut gizmo) in &mut point_clouds {
+        gizmo.obb = point_cloud.obb();
+    }
+}
+
+pub fn draw_point_cloud_obb_gizmos(
+    mut gizmos: Gizmos,
+    point_clouds: Query<(&GlobalTransform, &PointCloudObbGizmo)>,
+) {
+    for (transform, gizmo) in &point_clouds {
+        let Some((obb_transform, half_extents)) = gizmo.obb else {
+            continue;
+        };
+
+        // The cuboid gizmo is a unit cube.
+        let obb_transform = obb_transform.with_scale(half_extents * 2.0);
+        gizmos.cuboid(transform.mul_transform(obb_transform), YELLOW);
+    }
+}
+
+pub struct PointCloudDebugPlugin;
+
+impl Plugin for PointCloudDebugPlugin {
+    fn build(&self, app: &mut App) {
+        let shared_stats = SharedPointCloudBufferStats::default();
+        app
+            .register_type::<PointCloudBufferBar>()
+            .register_type::<PointCloudObbGizmo>()
+            .insert_resource(shared_stats.clone())
+            .add_systems(Startup, spawn_point_cloud_buffer_bar)
+            .add_systems(Update, (
+                update_point_cloud_buffer_bar,
+                (update_point_cloud_obb_gizmos, draw_point_cloud_obb_gizmos).chain(),
+            ));
+        app.sub_app_mut(RenderApp)
+            .insert_resource(shared_stats)
+            .add_systems(Render, update_point_cloud_buffer_stats.in_set(RenderSet::PrepareResources));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar_children(app: &mut App) -> Vec<Entity> {
+        let mut bars = app.world_mut().query_filtered::<&Children, With<PointCloudBufferBar>>();
+        bars.single(app.world()).to_vec()
+    }
+
+    #[test]
+    fn buffer_bar_is_rebuilt_only_when_allocations_change() {
+        let shared_stats = SharedPointCloudBufferStats::default();
+        *shared_stats.0.lock().unwrap() = PointCloudBufferStats {
+            capacity: 100,
+            used_regions: vec![(0, 10), (50, 20)],
+            ..default()
+        };
+
+        let mut app = App::new();
+        app
+            .insert_resource(shared_stats.clone())
+            .add_systems(Update, update_point_cloud_buffer_bar);
+        app.world_mut().spawn((InheritedVisibility::VISIBLE, PointCloudBufferBar::default()));
+
+        app.update();
+        let children = bar_children(&mut app);
+        assert_eq!(children.len(), 2);
+
+        app.update();
+        assert_eq!(bar_children(&mut app), children);
+
+        shared_stats.0.lock().unwrap().used_regions.push((80, 5));
+        app.update();
+        let rebuilt = bar_children(&mut app);
+        assert_eq!(rebuilt.len(), 3);
+        assert!(rebuilt.iter().all(|child| !children.contains(child)));
+    }
+
+    #[test]
+    fn obb_is_computed_when_the_gizmo_is_added_to_an_unchanged_cloud() {
+        let mut app = App::new();
+        app.add_systems(Update, update_point_cloud_obb_gizmos);
+        let entity = app.world_mut().spawn(PointCloud {
+            points: Arc::new(vec![Vec4::new(-1., 0., 0., 1.), Vec4::new(1., 0., 0., 1.)]),
+        }).id();
+        app.update();
+
+        app.world_mut().entity_mut(entity).insert(PointCloudObbGizmo::default());
+        app.update();
+        assert!(app.world().get::<PointCloudObbGizmo>(entity).unwrap().obb.is_some());
+    }
+}
@@ -0,0 +1,167 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{AsBindGroup, AsBindGroupShaderType, ShaderRef, ShaderType};
+use bevy::render::texture::GpuImage;
+
+use crate::point_cloud::{PointCloud, PointCloudAttribute, PointCloudMaterial, PointCloudMaterialPlugin};
+
+/// Estimates each point's local density into the cloud's `PointCloudAttribute`, for drawing with
+/// `PointCloudDensityMaterial`.
+#[derive(Clone, Debug, Component, Reflect)]
+#[reflect(Component)]
+pub struct PointCloudDensity {
+    /// Radius of the sphere around each point that its neighbours are counted in.
+    pub radius: f32,
+    /// Minimum seconds between estimates, since each builds a `KdTree` over the whole cloud.
+    pub interval: f32,
+    /// Whether the points have changed since the last estimate.
+    #[reflect(ignore)]
+    pub stale: bool,
+    /// When the last estimate was made, in seconds since startup.
+    #[reflect(ignore)]
+    pub estimated_at: Option<f32>,
+}
+
+impl Default for PointCloudDensity {
+    fn default() -> Self {
+        PointCloudDensity {
+            radius: 0.1,
+            interval: 1.0,
+            stale: true,
+            estimated_at: None,
+        }
+    }
+}
+
+pub fn update_point_cloud_density(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut point_clouds: Query<(Entity, Ref<PointCloud>, &mut PointCloudDensity)>,
+) {
+    let now = time.elapsed_seconds();
+    for (entity, point_cloud, mut density) in &mut point_clouds {
+        let stale = density.stale || density.is_changed() || point_cloud.is_changed();
+        let due = density.estimated_at.map_or(true, |estimated_at| now - estimated_at >= density.interval);
+        let density = density.bypass_change_detection();
+        density.stale = stale;
+        if !stale || !due {
+            continue;
+        }
+
+        density.stale = false;
+        density.estimated_at = Some(now);
+        commands.entity(entity).insert(PointCloudAttribute {
+            values: Arc::new(point_cloud.local_densities(density.radius)),
+        });
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+pub struct PointCloudDensityMaterialUniform {
+    pub log_density_min: f32,
+    pub log_density_max: f32,
+    pub hue_sparse: f32,
+    pub hue_dense: f32,
+    pub alpha: f32,
+}
+
+impl AsBindGroupShaderType<PointCloudDensityMaterialUniform> for PointCloudDensityMaterial {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<GpuImage>,
+    ) -> PointCloudDensityMaterialUniform {
+        let log_density_min = self.density_min.max(f32::MIN_POSITIVE).ln();
+        let log_density_max = self.density_max.max(f32::MIN_POSITIVE).ln().max(log_density_min + 1e-3);
+        PointCloudDensityMaterialUniform {
+            log_density_min,
+            log_density_max,
+            hue_sparse: self.hue_sparse,
+            hue_dense: self.hue_dense,
+            alpha: self.alpha.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Colours points as a heatmap of their `PointCloudDensity` estimate, on a log scale from
+/// `hue_sparse` at `density_min` to `hue_dense` at `density_max`.
+#[derive(Clone, Asset, AsBindGroup, Reflect)]
+#[uniform(0, PointCloudDensityMaterialUniform)]
+pub struct PointCloudDensityMaterial {
+    /// Points per cubic metre.
+    pub density_min: f32,
+    /// Points per cubic metre.
+    pub density_max: f32,
+    /// Hue in radians, wrapping every `2 * PI`.
+    pub hue_sparse: f32,
+    /// Hue in radians, wrapping every `2 * PI`.
+    pub hue_dense: f32,
+    pub alpha: f32,
+}
+
+impl Default for PointCloudDensityMaterial {
+    fn default() -> Self {
+        PointCloudDensityMaterial {
+            density_min: 250.0,
+            density_max: 25_000.0,
+            hue_sparse: PI * 4.0 / 3.0,
+            hue_dense: 0.0,
+            alpha: 0.5,
+        }
+    }
+}
+
+impl PointCloudMaterial for PointCloudDensityMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Path("shaders/point_cloud_density.wgsl".into())
+    }
+}
+
+/// Adds `PointCloudDensityMaterial` and keeps `PointCloudDensity` estimates up to date.
+pub struct PointCloudDensityPlugin;
+
+impl Plugin for PointCloudDensityPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<PointCloudDensity>()
+            .add_plugins(PointCloudMaterialPlugin::<PointCloudDensityMaterial>::default())
+            .add_systems(Update, update_point_cloud_density);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn attribute_len(app: &App, entity: Entity) -> Option<usize> {
+        app.world().get::<PointCloudAttribute>(entity).map(|attribute| attribute.values.len())
+    }
+
+    #[test]
+    fn density_is_estimated_at_most_once_per_interval() {
+        let mut app = App::new();
+        app
+            .init_resource::<Time>()
+            .add_systems(Update, update_point_cloud_density);
+        let entity = app.world_mut().spawn((
+            PointCloud { points: Arc::new(vec![Vec4::ONE; 2]) },
+            PointCloudDensity::default(),
+        )).id();
+
+        app.update();
+        assert_eq!(attribute_len(&app, entity), Some(2));
+
+        app.world_mut().get_mut::<PointCloud>(entity).unwrap().extend([Vec4::ZERO], None);
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.5));
+        app.update();
+        assert_eq!(attribute_len(&app, entity), Some(2));
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.6));
+        app.update();
+        assert_eq!(attribute_len(&app, entity), Some(3));
+    }
+}
@@ -13,6 +13,13 @@ pub struct PointCloudDistanceMaterialUniform {
     pub distance_max: f32,
     pub hue_min: f32,
     pub hue_max: f32,
+    pub reference_point: Vec3,
+    /// Non-zero to measure from the viewing camera instead of `reference_point`.
+    pub reference_camera: u32,
+    pub ring_spacing: f32,
+    pub ring_shade: f32,
+    pub falloff_mode: u32,
+    pub falloff_distance: f32,
 }
 
 impl AsBindGroupShaderType<PointCloudDistanceMaterialUniform> for PointCloudDistanceMaterial {
@@ -20,22 +27,77 @@ impl AsBindGroupShaderType<PointCloudDistanceMaterialUniform> for PointCloudDist
         &self,
         _images: &RenderAssets<GpuImage>,
     ) -> PointCloudDistanceMaterialUniform {
+        // smoothstep is undefined when its edges are equal or reversed.
+        let distance_min = self.distance_min.max(0.0);
+        let distance_max = self.distance_max.max(distance_min + MIN_DISTANCE_RANGE);
+        let (reference_point, reference_camera) = match self.reference {
+            DistanceReference::Camera => (Vec3::ZERO, 1),
+            DistanceReference::WorldOrigin => (Vec3::ZERO, 0),
+            DistanceReference::Fixed(point) => (point, 0),
+        };
         PointCloudDistanceMaterialUniform {
-            distance_min: self.distance_min,
-            distance_max: self.distance_max,
+            distance_min,
+            distance_max,
             hue_min: self.hue_min,
             hue_max: self.hue_max,
+            reference_point,
+            reference_camera,
+            ring_spacing: self.ring_spacing.max(0.0),
+            ring_shade: self.ring_shade.clamp(0.0, 1.0),
+            falloff_mode: match self.intensity_falloff {
+                IntensityFalloff::None => 0,
+                IntensityFalloff::Linear => 1,
+                IntensityFalloff::InverseSquare => 2,
+            },
+            falloff_distance: self.falloff_distance.max(MIN_DISTANCE_RANGE),
         }
     }
 }
 
+pub const MIN_DISTANCE_RANGE: f32 = 1e-3;
+
+/// The point that distances are measured from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub enum DistanceReference {
+    /// The camera currently rendering the point cloud.
+    #[default]
+    Camera,
+    WorldOrigin,
+    /// A fixed point in world space.
+    Fixed(Vec3),
+}
+
+/// How point opacity drops off with distance from the reference point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum IntensityFalloff {
+    #[default]
+    None,
+    /// Fades linearly to nothing at `falloff_distance`.
+    Linear,
+    /// `1 / (1 + (distance / falloff_distance)^2)`, so half intensity at `falloff_distance`.
+    InverseSquare,
+}
+
 #[derive(Clone, Asset, AsBindGroup, Reflect)]
 #[uniform(0, PointCloudDistanceMaterialUniform)]
 pub struct PointCloudDistanceMaterial {
+    /// Distance at which `hue_min` is used. Negative values are treated as zero.
     pub distance_min: f32,
+    /// Distance at which `hue_max` is used. Clamped to at least
+    /// `distance_min + MIN_DISTANCE_RANGE` when uploaded.
     pub distance_max: f32,
+    /// Hue in radians, wrapping every `2 * PI`.
     pub hue_min: f32,
+    /// Hue in radians, wrapping every `2 * PI`. May be less than `hue_min` to reverse the gradient.
     pub hue_max: f32,
+    pub reference: DistanceReference,
+    /// Width of each range ring. Alternate rings are darkened by `ring_shade`. Zero gives a
+    /// smooth gradient.
+    pub ring_spacing: f32,
+    /// Brightness multiplier applied to every other ring.
+    pub ring_shade: f32,
+    pub intensity_falloff: IntensityFalloff,
+    pub falloff_distance: f32,
     #[texture(1)]
     #[sampler(2)]
     pub base_color: Option<Handle<Image>>,
@@ -48,11 +110,47 @@ impl Default for PointCloudDistanceMaterial {
             distance_max: 100.0,
             hue_min: 0.0,
             hue_max: PI * 1.1,
+            reference: DistanceReference::Camera,
+            ring_spacing: 0.0,
+            ring_shade: 0.5,
+            intensity_falloff: IntensityFalloff::None,
+            falloff_distance: 50.0,
             base_color: None,
         }
     }
 }
 
+impl PointCloudDistanceMaterial {
+    pub fn with_range(distance_min: f32, distance_max: f32) -> PointCloudDistanceMaterial {
+        let mut material = PointCloudDistanceMaterial::default();
+        material.set_range(distance_min, distance_max);
+        material
+    }
+
+    pub fn set_range(&mut self, distance_min: f32, distance_max: f32) {
+        let distance_min = distance_min.max(0.0);
+        self.distance_min = distance_min;
+        self.distance_max = distance_max.max(distance_min + MIN_DISTANCE_RANGE);
+    }
+
+    /// Sets the range to `range`, widened on both ends by `margin` times its length.
+    pub fn fit_range(&mut self, (distance_min, distance_max): (f32, f32), margin: f32) {
+        let padding = (distance_max - distance_min) * margin;
+        self.set_range(distance_min - padding, distance_max + padding);
+    }
+}
+
+/// The nearest and furthest distances of `points` from `reference`, or `None` if there are no
+/// points.
+pub fn distance_range(reference: Vec3, points: impl IntoIterator<Item = Vec3>) -> Option<(f32, f32)> {
+    points.into_iter()
+        .map(|point| point.distance(reference))
+        .fold(None, |range, distance| match range {
+            Some((min, max)) => Some((distance.min(min), distance.max(max))),
+            None => Some((distance, distance)),
+        })
+}
+
 impl PointCloudMaterial for PointCloudDistanceMaterial {
     fn fragment_shader() -> ShaderRef {
         ShaderRef::Path("shaders/point_cloud_distance.wgsl".into())
@@ -0,0 +1,482 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy::math::vec3;
+use bevy::prelude::*;
+
+use crate::point_cloud::{DEFAULT_POINT_SIZE, PointCloud};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PcdFormat {
+    #[default]
+    Ascii,
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    /// Converts a point from this up-axis convention into Bevy's right-handed, Y-up frame.
+    pub fn to_y_up(self, point: Vec3) -> Vec3 {
+        match self {
+            UpAxis::Y => point,
+            UpAxis::Z => vec3(point.x, point.z, -point.y),
+        }
+    }
+}
+
+/// Options for the point cloud loaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LoadOptions {
+    pub up_axis: UpAxis,
+    /// Keep only every `decimate`th point of the file, for quick previews of huge files. Skipped
+    /// points are never stored. Zero and one keep every point.
+    pub decimate: usize,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            up_axis: UpAxis::Y,
+            decimate: 1,
+        }
+    }
+}
+
+/// Writes `point_cloud` to a PCD file. If `selection` is given, only the points at those indices
+/// are written, in that order.
+pub fn export_pcd(
+    path: impl AsRef<Path>,
+    point_cloud: &PointCloud,
+    selection: Option<&[usize]>,
+    format: PcdFormat,
+) -> io::Result<()> {
+    let selected;
+    let points: &[Vec4] = match selection {
+        Some(selection) => {
+            selected = select_points(&point_cloud.points, selection)?;
+            &selected
+        }
+        None => &point_cloud.points,
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_pcd(&mut writer, points, format)?;
+    writer.flush()
+}
+
+pub fn select_points(points: &[Vec4], selection: &[usize]) -> io::Result<Vec<Vec4>> {
+    selection.iter()
+        .map(|&index| points.get(index).copied().ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("selected point {} is out of range for {} points", index, points.len()),
+        )))
+        .collect()
+}
+
+pub fn write_pcd(writer: &mut impl Write, points: &[Vec4], format: PcdFormat) -> io::Result<()> {
+    writeln!(writer, "# .PCD v0.7 - Point Cloud Data file format")?;
+    writeln!(writer, "VERSION 0.7")?;
+    writeln!(writer, "FIELDS x y z size")?;
+    writeln!(writer, "SIZE 4 4 4 4")?;
+    writeln!(writer, "TYPE F F F F")?;
+    writeln!(writer, "COUNT 1 1 1 1")?;
+    writeln!(writer, "WIDTH {}", points.len())?;
+    writeln!(writer, "HEIGHT 1")?;
+    writeln!(writer, "VIEWPOINT 0 0 0 1 0 0 0")?;
+    writeln!(writer, "POINTS {}", points.len())?;
+
+    match format {
+        PcdFormat::Ascii => {
+            writeln!(writer, "DATA ascii")?;
+            for point in points {
+                writeln!(writer, "{} {} {} {}", point.x, point.y, point.z, point.w)?;
+            }
+        }
+        PcdFormat::Binary => {
+            writeln!(writer, "DATA binary")?;
+            for point in points {
+                for component in point.to_array() {
+                    writer.write_all(&component.to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn load_pcd(path: impl AsRef<Path>, options: LoadOptions) -> io::Result<PointCloud> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_pcd(&mut reader, options)
+}
+
+pub fn read_pcd(reader: &mut impl BufRead, options: LoadOptions) -> io::Result<PointCloud> {
+    let up_axis = options.up_axis;
+    let decimate = options.decimate.max(1);
+    let header = PcdHeader::read(reader)?;
+    let x = header.require_field("x")?;
+    let y = header.require_field("y")?;
+    let z = header.require_field("z")?;
+    let size = header.field("size");
+
+    let mut points = Vec::with_capacity(header.num_points.div_ceil(decimate));
+    match header.data.as_str() {
+        "ascii" => {
+            let mut line = String::new();
+            let mut line_number = header.num_lines;
+            let mut index = 0;
+            while index < header.num_points {
+                line.clear();
+                line_number += 1;
+                if reader.read_line(&mut line)? == 0 {
+                    break;
+                }
+
+                let values = line.split_whitespace().collect::<Vec<_>>();
+                if values.is_empty() {
+                    continue;
+                }
+                index += 1;
+                if (index - 1) % decimate != 0 {
+                    continue;
+                }
+
+                let read = |field: &PcdField| -> io::Result<f32> {
+                    values.get(field.column)
+                        .and_then(|value| value.parse::<f32>().ok())
+                        .ok_or_else(|| invalid_data(format!("invalid value for field '{}' on line {}", field.name, line_number)))
+                };
+                let point = Vec4::new(
+                    read(x)?,
+                    read(y)?,
+                    read(z)?,
+                    size.map(read).transpose()?.unwrap_or(DEFAULT_POINT_SIZE),
+                );
+                push_point(&mut points, point, up_axis);
+            }
+        }
+        "binary" => {
+            let mut record = vec![0u8; header.record_size];
+            for index in 0..header.num_points {
+                reader.read_exact(&mut record)?;
+                if index % decimate != 0 {
+                    continue;
+                }
+                let point = Vec4::new(
+                    x.decode(&record),
+                    y.decode(&record),
+                    z.decode(&record),
+                    size.map(|field| field.decode(&record)).unwrap_or(DEFAULT_POINT_SIZE),
+                );
+                push_point(&mut points, point, up_axis);
+            }
+        }
+        other => return Err(invalid_data(format!("unsupported PCD data format '{}'", other))),
+    }
+
+    Ok(PointCloud {
+        points: Arc::new(points),
+    })
+}
+
+pub fn load_xyz(path: impl AsRef<Path>, has_color: bool, options: LoadOptions) -> io::Result<PointCloud> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_xyz(&mut reader, has_color, options)
+}
+
+/// Reads plain-text points, one `x y z` per line, separated by whitespace or commas. Lines
+/// starting with `#` and blank lines are skipped. If `has_color` is set, each line must also
+/// have `r g b` columns; they are checked but not stored, since point clouds have no colour.
+pub fn read_xyz(reader: &mut impl BufRead, has_color: bool, options: LoadOptions) -> io::Result<PointCloud> {
+    let decimate = options.decimate.max(1);
+    let num_columns = if has_color { 6 } else { 3 };
+    let mut points = Vec::new();
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut index = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        index += 1;
+        if (index - 1) % decimate != 0 {
+            continue;
+        }
+
+        let values = trimmed.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid_data(format!("invalid number on line {}", line_number)))?;
+        if values.len() < num_columns {
+            return Err(invalid_data(format!(
+                "expected {} values on line {}, found {}", num_columns, line_number, values.len(),
+            )));
+        }
+
+        push_point(&mut points, Vec4::new(values[0], values[1], values[2], DEFAULT_POINT_SIZE), options.up_axis);
+    }
+
+    Ok(PointCloud {
+        points: Arc::new(points),
+    })
+}
+
+fn push_point(points: &mut Vec<Vec4>, point: Vec4, up_axis: UpAxis) {
+    // PCL marks invalid points in organised clouds with NaN coordinates.
+    if point.is_finite() {
+        points.push(up_axis.to_y_up(point.truncate()).extend(point.w));
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct PcdField {
+    name: String,
+    kind: u8,
+    size: usize,
+    offset: usize,
+    column: usize,
+}
+
+impl PcdField {
+    fn decode(&self, record: &[u8]) -> f32 {
+        let bytes = &record[self.offset..(self.offset + self.size)];
+        match (self.kind, self.size) {
+            (b'F', 4) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            (b'F', 8) => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'I', 1) => i8::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'I', 2) => i16::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'I', 4) => i32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'I', 8) => i64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'U', 1) => bytes[0] as f32,
+            (b'U', 2) => u16::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'U', 4) => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            (b'U', 8) => u64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            _ => unreachable!("field types are validated when reading the header"),
+        }
+    }
+}
+
+struct PcdHeader {
+    fields: Vec<PcdField>,
+    num_points: usize,
+    record_size: usize,
+    num_lines: usize,
+    data: String,
+}
+
+impl PcdHeader {
+    fn read(reader: &mut impl BufRead) -> io::Result<PcdHeader> {
+        let mut names = Vec::new();
+        let mut sizes = Vec::new();
+        let mut kinds = Vec::new();
+        let mut counts = Vec::new();
+        let mut width = None;
+        let mut height = None;
+        let mut num_points = None;
+        let mut num_lines = 0;
+        let mut line = String::new();
+
+        let data = loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("PCD header has no DATA line"));
+            }
+            num_lines += 1;
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = trimmed.split_whitespace();
+            let key = parts.next().unwrap_or_default().to_ascii_uppercase();
+            let values = parts.collect::<Vec<_>>();
+            let parse_all = |values: &[&str]| {
+                values.iter()
+                    .map(|value| value.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid_data(format!("invalid {} on line {}", key, num_lines)))
+            };
+
+            match key.as_str() {
+                "FIELDS" => names = values.iter().map(|value| value.to_string()).collect(),
+                "SIZE" => sizes = parse_all(&values)?,
+                "TYPE" => kinds = values.iter().map(|value| value.as_bytes()[0].to_ascii_uppercase()).collect(),
+                "COUNT" => counts = parse_all(&values)?,
+                "WIDTH" => width = parse_all(&values)?.first().copied(),
+                "HEIGHT" => height = parse_all(&values)?.first().copied(),
+                "POINTS" => num_points = parse_all(&values)?.first().copied(),
+                "DATA" => break values.first().map(|value| value.to_ascii_lowercase()).unwrap_or_default(),
+                _ => {}
+            }
+        };
+
+        if counts.is_empty() {
+            counts = vec![1; names.len()];
+        }
+        if sizes.len() != names.len() || kinds.len() != names.len() || counts.len() != names.len() {
+            return Err(invalid_data("PCD FIELDS, SIZE, TYPE and COUNT lengths differ"));
+        }
+
+        let mut fields = Vec::with_capacity(names.len());
+        let mut offset = 0;
+        let mut column = 0;
+        for (((name, size), kind), count) in names.into_iter().zip(sizes).zip(kinds).zip(counts) {
+            let valid = match kind {
+                b'F' => matches!(size, 4 | 8),
+                b'I' | b'U' => matches!(size, 1 | 2 | 4 | 8),
+                _ => false,
+            };
+            if !valid {
+                return Err(invalid_data(format!("unsupported type for PCD field '{}'", name)));
+            }
+
+            fields.push(PcdField {
+                name,
+                kind,
+                size,
+                offset,
+                column,
+            });
+            offset += size * count;
+            column += count;
+        }
+
+        let num_points = num_points
+            .or_else(|| Some(width? * height.unwrap_or(1)))
+            .ok_or_else(|| invalid_data("PCD header has no POINTS or WIDTH"))?;
+
+        Ok(PcdHeader {
+            fields,
+            num_points,
+            record_size: offset,
+            num_lines,
+            data,
+        })
+    }
+
+    fn field(&self, name: &str) -> Option<&PcdField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    fn require_field(&self, name: &str) -> io::Result<&PcdField> {
+        self.field(name)
+            .ok_or_else(|| invalid_data(format!("PCD file has no '{}' field", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn z_up_basis_maps_to_y_up() {
+        assert_eq!(UpAxis::Z.to_y_up(Vec3::X), Vec3::X);
+        assert_eq!(UpAxis::Z.to_y_up(Vec3::Y), Vec3::NEG_Z);
+        assert_eq!(UpAxis::Z.to_y_up(Vec3::Z), Vec3::Y);
+    }
+
+    #[test]
+    fn z_up_conversion_keeps_handedness() {
+        let x = UpAxis::Z.to_y_up(Vec3::X);
+        let y = UpAxis::Z.to_y_up(Vec3::Y);
+        let z = UpAxis::Z.to_y_up(Vec3::Z);
+        assert_eq!(x.cross(y), z);
+    }
+
+    #[test]
+    fn y_up_is_unchanged() {
+        let point = vec3(1., 2., 3.);
+        assert_eq!(UpAxis::Y.to_y_up(point), point);
+    }
+
+    fn xyz(text: &str, has_color: bool, options: LoadOptions) -> io::Result<Vec<Vec3>> {
+        let cloud = read_xyz(&mut text.as_bytes(), has_color, options)?;
+        Ok(cloud.points.iter().map(|point| point.truncate()).collect())
+    }
+
+    #[test]
+    fn xyz_reads_comma_and_space_separated_points() {
+        let points = xyz("1,2,3\n4 5 6\n7, 8\t9\n", false, LoadOptions::default()).unwrap();
+        assert_eq!(points, vec![vec3(1., 2., 3.), vec3(4., 5., 6.), vec3(7., 8., 9.)]);
+    }
+
+    #[test]
+    fn xyz_skips_comments_and_blank_lines() {
+        let points = xyz("# header\n\n1 2 3\n   \n# 4 5 6\n7 8 9\n", false, LoadOptions::default()).unwrap();
+        assert_eq!(points, vec![vec3(1., 2., 3.), vec3(7., 8., 9.)]);
+    }
+
+    #[test]
+    fn xyz_colour_columns_are_required_but_not_stored() {
+        let points = xyz("1 2 3 255 0 0\n", true, LoadOptions::default()).unwrap();
+        assert_eq!(points, vec![vec3(1., 2., 3.)]);
+
+        let error = xyz("1 2 3 255 0 0\n4 5 6\n", true, LoadOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 2"), "{error}");
+    }
+
+    #[test]
+    fn xyz_malformed_line_reports_its_line_number() {
+        let error = xyz("# header\n1 2 3\n4 five 6\n", false, LoadOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 3"), "{error}");
+    }
+
+    #[test]
+    fn xyz_applies_load_options() {
+        let options = LoadOptions {
+            up_axis: UpAxis::Z,
+            decimate: 2,
+        };
+        let points = xyz("1 2 3\n# skipped\n4 5 6\n7 8 9\n", false, options).unwrap();
+        assert_eq!(points, vec![UpAxis::Z.to_y_up(vec3(1., 2., 3.)), UpAxis::Z.to_y_up(vec3(7., 8., 9.))]);
+    }
+
+    fn decimated_pcd(format: PcdFormat, decimate: usize) -> Vec<Vec4> {
+        let points = (0..10)
+            .map(|index| Vec4::new(index as f32, 0., 0., 1.))
+            .collect::<Vec<_>>();
+        let mut file = Vec::new();
+        write_pcd(&mut file, &points, format).unwrap();
+        let options = LoadOptions {
+            decimate,
+            ..default()
+        };
+        read_pcd(&mut file.as_slice(), options).unwrap().points.to_vec()
+    }
+
+    #[test]
+    fn ascii_and_binary_pcd_decimate_the_same_points() {
+        let kept = |points: Vec<Vec4>| points.iter().map(|point| point.x as usize).collect::<Vec<_>>();
+        assert_eq!(kept(decimated_pcd(PcdFormat::Ascii, 3)), vec![0, 3, 6, 9]);
+        assert_eq!(kept(decimated_pcd(PcdFormat::Binary, 3)), vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn pcd_decimate_zero_keeps_every_point() {
+        for format in [PcdFormat::Ascii, PcdFormat::Binary] {
+            assert_eq!(decimated_pcd(format, 0), decimated_pcd(format, 1));
+            assert_eq!(decimated_pcd(format, 0).len(), 10);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+/// A static 3D tree over a set of points, stored implicitly as a permuted index array.
+pub struct KdTree {
+    points: Vec<Vec3>,
+    indices: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn new(points: impl IntoIterator<Item = Vec3>) -> KdTree {
+        let points = points.into_iter().collect::<Vec<_>>();
+        let mut indices = (0..points.len()).collect::<Vec<_>>();
+        build(&points, &mut indices, 0);
+        KdTree {
+            points,
+            indices,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn point(&self, index: usize) -> Vec3 {
+        self.points[index]
+    }
+
+    /// Returns the index of the closest point and its squared distance from `query`.
+    pub fn nearest(&self, query: Vec3) -> Option<(usize, f32)> {
+        let mut best = None;
+        self.nearest_in(&self.indices, 0, query, &mut best);
+        best
+    }
+
+    /// Calls `f` with the index of every point within `radius` of `center`.
+    pub fn for_each_within(&self, center: Vec3, radius: f32, mut f: impl FnMut(usize)) {
+        self.within_in(&self.indices, 0, center, radius, &mut f);
+    }
+
+    pub fn within(&self, center: Vec3, radius: f32) -> Vec<usize> {
+        let mut result = Vec::new();
+        self.for_each_within(center, radius, |index| result.push(index));
+        result
+    }
+
+    fn nearest_in(&self, indices: &[usize], depth: usize, query: Vec3, best: &mut Option<(usize, f32)>) {
+        if indices.is_empty() {
+            return;
+        }
+
+        let axis = depth % 3;
+        let mid = indices.len() / 2;
+        let index = indices[mid];
+        let point = self.points[index];
+        let distance_squared = point.distance_squared(query);
+        if best.map_or(true, |(_, best_distance)| distance_squared < best_distance) {
+            *best = Some((index, distance_squared));
+        }
+
+        let delta = query[axis] - point[axis];
+        let (near, far) = if delta < 0. {
+            (&indices[..mid], &indices[(mid + 1)..])
+        } else {
+            (&indices[(mid + 1)..], &indices[..mid])
+        };
+        self.nearest_in(near, depth + 1, query, best);
+        if best.map_or(true, |(_, best_distance)| delta * delta < best_distance) {
+            self.nearest_in(far, depth + 1, query, best);
+        }
+    }
+
+    fn within_in(&self, indices: &[usize], depth: usize, center: Vec3, radius: f32, f: &mut impl FnMut(usize)) {
+        if indices.is_empty() {
+            return;
+        }
+
+        let axis = depth % 3;
+        let mid = indices.len() / 2;
+        let index = indices[mid];
+        let point = self.points[index];
+        if point.distance_squared(center) <= radius * radius {
+            f(index);
+        }
+
+        let delta = center[axis] - point[axis];
+        if delta <= radius {
+            self.within_in(&indices[..mid], depth + 1, center, radius, f);
+        }
+        if delta >= -radius {
+            self.within_in(&indices[(mid + 1)..], depth + 1, center, radius, f);
+        }
+    }
+}
+
+fn build(points: &[Vec3], indices: &mut [usize], depth: usize) {
+    if indices.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % 3;
+    let mid = indices.len() / 2;
+    indices.select_nth_unstable_by(mid, |a, b| points[*a][axis].total_cmp(&points[*b][axis]));
+    let (left, right) = indices.split_at_mut(mid);
+    build(points, left, depth + 1);
+    build(points, &mut right[1..], depth + 1);
+}
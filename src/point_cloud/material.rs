@@ -6,14 +6,14 @@ use bevy::pbr::{MeshPipelineViewLayoutKey, SetMeshViewBindGroup};
 use bevy::prelude::*;
 use bevy::render::extract_instances::{ExtractedInstances, ExtractInstancesPlugin};
 use bevy::render::render_asset::{prepare_assets, PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssets};
-use bevy::render::render_resource::{AsBindGroup, AsBindGroupError, BindGroup, BindGroupLayout, OwnedBindingResource, PipelineCache, RenderPipelineDescriptor, ShaderRef, SpecializedRenderPipeline, SpecializedRenderPipelines};
+use bevy::render::render_resource::{AsBindGroup, AsBindGroupError, BindGroup, BindGroupLayout, CachedRenderPipelineId, OwnedBindingResource, PipelineCache, RenderPipelineDescriptor, ShaderRef, SpecializedRenderPipeline, SpecializedRenderPipelines};
 use bevy::render::{Render, RenderApp, RenderSet};
-use bevy::render::render_phase::{AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases};
+use bevy::render::render_phase::{AddRenderCommand, BinnedRenderPhase, DrawFunctionId, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases};
 use bevy::render::renderer::RenderDevice;
 use bevy::render::texture::{FallbackImage, GpuImage};
-use bevy::render::view::ExtractedView;
-use crate::point_cloud::{DrawPointCloudMesh, PointCloudInstances, PointCloudPipeline, PointCloudPipelineKey, SetPointCloudBindGroup};
-use crate::transparency::{OrderIndependentTransparent3d, OrderIndependentTransparent3dBinKey};
+use bevy::render::view::{ExtractedView, VisibleEntities};
+use crate::point_cloud::{DrawPointCloudMesh, PointCloud, PointCloudInstance, PointCloudInstances, PointCloudPipeline, PointCloudPipelineKey, SetPointCloudBindGroup};
+use crate::transparency::{OrderIndependentTransparencySettings, OrderIndependentTransparent3d, OrderIndependentTransparent3dBinKey};
 
 pub trait PointCloudMaterial: Asset + AsBindGroup + Clone + Sized {
     fn vertex_shader() -> ShaderRef {
@@ -274,13 +274,14 @@ pub fn queue_material_point_clouds<M: PointCloudMaterial>(
     draw_functions: Res<DrawFunctions<OrderIndependentTransparent3d>>,
     point_cloud_pipeline: Res<PointCloudMaterialPipeline<M>>,
     msaa: Res<Msaa>,
+    transparency_settings: Res<OrderIndependentTransparencySettings>,
     mut pipelines: ResMut<SpecializedRenderPipelines<PointCloudMaterialPipeline<M>>>,
     pipeline_cache: Res<PipelineCache>,
     point_cloud_instances: Res<PointCloudInstances>,
     render_materials: Res<RenderAssets<PreparedPointCloudMaterial<M>>>,
     render_material_instances: Res<RenderMaterialInstances<M>>,
     mut transparent_phases: ResMut<ViewBinnedRenderPhases<OrderIndependentTransparent3d>>,
-    mut views: Query<Entity, With<ExtractedView>>,
+    mut views: Query<(Entity, &VisibleEntities), With<ExtractedView>>,
 ) where <M as AsBindGroup>::Data: Clone + Hash + Eq {
     let draw_point_cloud = draw_functions.read().id::<DrawPointCloudMaterial<M>>();
     let view_key = if msaa.samples() > 1 {
@@ -291,31 +292,162 @@ pub fn queue_material_point_clouds<M: PointCloudMaterial>(
     let point_key = PointCloudPipelineKey {
         msaa_samples: msaa.samples(),
         view_key,
+        mode: transparency_settings.mode,
+        selection: false,
     };
-    for view_entity in &mut views {
+    for (view_entity, visible_entities) in &mut views {
         let Some(transparent_phase) = transparent_phases.get_mut(&view_entity) else {
             continue;
         };
 
-        for entity in point_cloud_instances.keys().copied() {
-            let Some(material_asset_id) = render_material_instances.get(&entity) else {
-                continue;
-            };
-            let Some(material) = render_materials.get(*material_asset_id) else {
-                continue;
-            };
-
-            let pipeline_key = PointCloudMaterialPipelineKey {
-                point_key,
-                bind_group_data: material.key.clone(),
-            };
-            let pipeline = pipelines
-                .specialize(&pipeline_cache, &point_cloud_pipeline, pipeline_key);
-            let key = OrderIndependentTransparent3dBinKey {
-                pipeline,
-                draw_function: draw_point_cloud,
-            };
-            transparent_phase.add(key, entity, true);
+        // The visible set is already filtered by RenderLayers.
+        add_material_point_clouds(
+            transparent_phase,
+            visible_entities.iter::<With<PointCloud>>(),
+            &point_cloud_instances,
+            &render_material_instances,
+            draw_point_cloud,
+            |material_asset_id, instance| {
+                let material = render_materials.get(material_asset_id)?;
+                let pipeline_key = PointCloudMaterialPipelineKey {
+                    point_key: PointCloudPipelineKey {
+                        selection: instance.selection,
+                        ..point_key
+                    },
+                    bind_group_data: material.key.clone(),
+                };
+                Some(pipelines.specialize(&pipeline_cache, &point_cloud_pipeline, pipeline_key))
+            },
+        );
+    }
+}
+
+/// Bins the visible clouds that have points and an `M` material into `phase`, keyed by pipeline,
+/// material and point buffer chunk. Clouds without an `M` material are skipped, so each cloud is
+/// only queued by the material it uses. `pipeline` returns the pipeline for a material and
+/// instance, or `None` for materials that aren't prepared yet.
+pub fn add_material_point_clouds<'a, M: PointCloudMaterial>(
+    phase: &mut BinnedRenderPhase<OrderIndependentTransparent3d>,
+    visible_entities: impl IntoIterator<Item = &'a Entity>,
+    point_cloud_instances: &PointCloudInstances,
+    render_material_instances: &RenderMaterialInstances<M>,
+    draw_function: DrawFunctionId,
+    mut pipeline: impl FnMut(AssetId<M>, &PointCloudInstance) -> Option<CachedRenderPipelineId>,
+) {
+    for &entity in visible_entities {
+        let Some(instance) = point_cloud_instances.get(&entity) else {
+            continue;
+        };
+        if instance.num_points == 0 {
+            continue;
+        }
+
+        let Some(&material_asset_id) = render_material_instances.get(&entity) else {
+            continue;
+        };
+        let Some(pipeline) = pipeline(material_asset_id, instance) else {
+            continue;
+        };
+
+        let key = OrderIndependentTransparent3dBinKey {
+            pipeline,
+            draw_function,
+            asset_id: material_asset_id.untyped(),
+            chunk: instance.allocation.as_ref().map_or(0, |allocation| allocation.chunk),
+        };
+        phase.add(key, entity, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::point_cloud::order_material::PointCloudOrderMaterial;
+    use crate::point_cloud::PointCloudInstance;
+    use super::*;
+
+    fn draw_function() -> DrawFunctionId {
+        let mut app = App::new();
+        app
+            .init_resource::<DrawFunctions<OrderIndependentTransparent3d>>()
+            .add_render_command::<OrderIndependentTransparent3d, SetItemPipeline>();
+        let draw_function = app.world().resource::<DrawFunctions<OrderIndependentTransparent3d>>()
+            .read()
+            .id::<SetItemPipeline>();
+        draw_function
+    }
+
+    fn instance() -> PointCloudInstance {
+        PointCloudInstance {
+            world_from_local: (&Transform::IDENTITY.compute_affine()).into(),
+            previous_world_from_local: (&Transform::IDENTITY.compute_affine()).into(),
+            num_points: 16,
+            allocation: None,
+            attribute: false,
+            selection: false,
+            sorted: false,
+            sorted_eye: None,
+        }
+    }
+
+    fn queued(phase: &BinnedRenderPhase<OrderIndependentTransparent3d>) -> Vec<Entity> {
+        phase.batchable_mesh_values.values().flatten().copied().collect()
+    }
+
+    #[test]
+    fn only_clouds_with_a_material_are_queued_once() {
+        let plain = Entity::from_raw(1);
+        let textured = Entity::from_raw(2);
+        let mut point_cloud_instances = PointCloudInstances::default();
+        point_cloud_instances.insert(plain, instance());
+        point_cloud_instances.insert(textured, instance());
+        let mut material_instances = RenderMaterialInstances::<PointCloudOrderMaterial>::default();
+        material_instances.insert(textured, Handle::weak_from_u128(1).id());
+
+        let mut phase = BinnedRenderPhase::default();
+        add_material_point_clouds(
+            &mut phase,
+            &[plain, textured],
+            &point_cloud_instances,
+            &material_instances,
+            draw_function(),
+            |_, _| Some(CachedRenderPipelineId::INVALID),
+        );
+        assert_eq!(queued(&phase), vec![textured]);
+    }
+
+    #[test]
+    fn clouds_with_different_materials_are_binned_apart() {
+        let clouds = [Entity::from_raw(1), Entity::from_raw(2), Entity::from_raw(3)];
+        let red = Handle::<PointCloudOrderMaterial>::weak_from_u128(1).id();
+        let blue = Handle::<PointCloudOrderMaterial>::weak_from_u128(2).id();
+        let mut point_cloud_instances = PointCloudInstances::default();
+        let mut material_instances = RenderMaterialInstances::<PointCloudOrderMaterial>::default();
+        for (entity, material) in clouds.into_iter().zip([red, blue, red]) {
+            point_cloud_instances.insert(entity, instance());
+            material_instances.insert(entity, material);
+        }
+
+        let mut phase = BinnedRenderPhase::default();
+        add_material_point_clouds(
+            &mut phase,
+            &clouds,
+            &point_cloud_instances,
+            &material_instances,
+            draw_function(),
+            |_, _| Some(CachedRenderPipelineId::INVALID),
+        );
+
+        // Both materials share a pipeline, so only the asset id keeps them apart.
+        assert_eq!(phase.batchable_mesh_keys.len(), 2);
+        for key in &phase.batchable_mesh_keys {
+            let mut entities = phase.batchable_mesh_values[key].clone();
+            entities.sort();
+            if key.asset_id == red.untyped() {
+                assert_eq!(entities, vec![clouds[0], clouds[2]]);
+            } else {
+                assert_eq!(key.asset_id, blue.untyped());
+                assert_eq!(entities, vec![clouds[1]]);
+            }
         }
     }
 }
@@ -1,7 +1,12 @@
+use std::f32::consts::PI;
+use std::future::{Future, poll_fn};
 use std::mem::size_of;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Poll, Waker};
 
-use bevy::ecs::entity::EntityHashMap;
+use bevy::color::ColorToComponents;
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet};
 use bevy::ecs::query::QueryItem;
 use bevy::ecs::system::lifetimeless::{SRes, SResMut};
 use bevy::ecs::system::SystemParamItem;
@@ -9,26 +14,44 @@ use bevy::math::Affine3;
 use bevy::pbr::{MeshInputUniform, MeshPipeline, MeshPipelineViewLayoutKey, MeshPipelineViewLayouts, PreviousGlobalTransform};
 use bevy::prelude::*;
 use bevy::render::{Extract, Render, RenderApp, RenderSet};
+use bevy::render::mesh::PrimitiveTopology;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
 use bevy::render::batching::{GetBatchData, GetFullBatchData};
 use bevy::render::batching::gpu_preprocessing::IndirectParametersBuffer;
 use bevy::render::batching::no_gpu_preprocessing::{BatchedInstanceBuffer, clear_batched_cpu_instance_buffers, write_batched_instance_buffer};
-use bevy::render::camera::ExtractedCamera;
-use bevy::render::render_phase::{BinnedRenderPhasePlugin, PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass, ViewBinnedRenderPhases};
-use bevy::render::render_resource::{BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Extent3d, FragmentState, GpuArrayBuffer, MultisampleState, PrimitiveState, RawBufferVec, RenderPipelineDescriptor, ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, VertexState};
-use bevy::render::render_resource::binding_types::storage_buffer_read_only;
+use bevy::render::render_phase::{BinnedRenderPhasePlugin, PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass};
+use bevy::render::render_resource::{BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState, CommandEncoderDescriptor, ColorWrites, FragmentState, GpuArrayBuffer, Maintain, MapMode, MultisampleState, PrimitiveState, RawBufferVec, RenderPipelineDescriptor, ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat, UniformBuffer, VertexState};
+use bevy::render::render_resource::binding_types::{storage_buffer_read_only, uniform_buffer};
 use bevy::render::renderer::{RenderDevice, RenderQueue};
-use bevy::render::texture::{ColorAttachment, TextureCache};
 use bevy::render::view::{check_visibility, VisibilitySystems};
+use bevy::utils::HashMap;
 use bytemuck::{Pod, Zeroable};
 use nonmax::NonMaxU32;
 use offset_allocator::{Allocation, Allocator};
+use parry3d::na::{Matrix3, Vector3};
 
 pub use material::{PointCloudMaterial, PointCloudMaterialPipelineKey, PointCloudMaterialPipeline, PointCloudMaterialPlugin, PreparedPointCloudMaterial, SetPointCloudMaterialBindGroup, queue_material_point_clouds};
 
-use crate::transparency::OrderIndependentTransparent3d;
+use crate::point_cloud::kdtree::KdTree;
+use crate::transparency::{OrderIndependentTransparencySettings, OrderIndependentTransparent3d, TransparencyMode};
 
 mod material;
+pub mod debug;
+pub mod density_material;
 pub mod distance_material;
+pub mod order_material;
+pub mod io;
+pub mod kdtree;
+pub mod offscreen;
+pub mod register;
+pub mod source;
+
+pub const DEFAULT_POINT_SIZE: f32 = 0.025;
+pub const DEFAULT_MAX_POINTS: usize = 10_000_000;
+/// How far the camera has to move, in a cloud's local space, before the cloud's draw order is
+/// sorted again for `TransparencyMode::Sorted`.
+pub const DRAW_ORDER_RESORT_DISTANCE: f32 = 0.05;
 
 #[derive(Clone, Debug, Default, Reflect, Component)]
 #[reflect(Component)]
@@ -36,23 +59,438 @@ pub struct PointCloud {
     pub points: Arc<Vec<Vec4>>,
 }
 
+impl PointCloud {
+    pub fn apply_transform(&mut self, transform: &Transform) {
+        let matrix = transform.compute_affine();
+        for point in Arc::make_mut(&mut self.points) {
+            *point = matrix.transform_point3(point.truncate()).extend(point.w);
+        }
+    }
+
+    /// Appends `points`, stopping once the cloud holds `limit` points. Returns how many were added.
+    pub fn extend(&mut self, points: impl IntoIterator<Item = Vec4>, limit: Option<usize>) -> usize {
+        let remaining = limit.map_or(usize::MAX, |limit| limit.saturating_sub(self.points.len()));
+        let mut points = points.into_iter().take(remaining).peekable();
+        // Avoid cloning shared points when there's nothing to add.
+        if points.peek().is_none() {
+            return 0;
+        }
+
+        let target = Arc::make_mut(&mut self.points);
+        let before = target.len();
+        target.extend(points);
+        target.len() - before
+    }
+
+    /// Indices of the points within `radius` of `center`.
+    ///
+    /// This is a linear scan, since clouds don't keep a spatial index. Build a `kdtree::KdTree`
+    /// when running many queries against the same points.
+    pub fn points_within(&self, center: Vec3, radius: f32) -> impl Iterator<Item = usize> + '_ {
+        let radius_squared = radius * radius;
+        self.points.iter()
+            .enumerate()
+            .filter(move |(_, point)| point.truncate().distance_squared(center) <= radius_squared)
+            .map(|(index, _)| index)
+    }
+
+    /// Each point's local density in points per cubic metre, from the number of points within
+    /// `radius` of it, itself included.
+    pub fn local_densities(&self, radius: f32) -> Vec<f32> {
+        if radius <= 0.0 {
+            return vec![0.0; self.points.len()];
+        }
+
+        let tree = KdTree::new(self.points.iter().map(|point| point.truncate()));
+        let volume = 4.0 / 3.0 * PI * radius.powi(3);
+        (0..tree.len())
+            .map(|index| {
+                let mut count = 0;
+                tree.for_each_within(tree.point(index), radius, |_| count += 1);
+                count as f32 / volume
+            })
+            .collect()
+    }
+
+    /// The mean point position, or zero for an empty cloud.
+    pub fn centroid(&self) -> Vec3 {
+        if self.points.is_empty() {
+            return Vec3::ZERO;
+        }
+
+        self.points.iter()
+            .fold(Vec3::ZERO, |acc, point| acc + point.truncate())
+            / self.points.len() as f32
+    }
+
+    /// The principal axes of the points, in order of decreasing variance, from the
+    /// eigenvectors of their covariance. The axes are unit length and form a right-handed
+    /// basis. An empty cloud, or one the decomposition can't give a basis for, returns the world
+    /// axes.
+    pub fn principal_axes(&self) -> (Vec3, Vec3, Vec3) {
+        if self.points.is_empty() {
+            return (Vec3::X, Vec3::Y, Vec3::Z);
+        }
+
+        let centroid = self.centroid();
+        let covariance = self.points.iter()
+            .fold(Matrix3::zeros(), |acc, point| {
+                let d = point.truncate() - centroid;
+                let d = Vector3::new(d.x, d.y, d.z);
+                acc + d * d.transpose()
+            })
+            / self.points.len() as f32;
+
+        let eigen = covariance.symmetric_eigen();
+        let mut order = [0, 1, 2];
+        order.sort_by(|&a, &b| eigen.eigenvalues[b].total_cmp(&eigen.eigenvalues[a]));
+        let axis = |index: usize| {
+            let column = eigen.eigenvectors.column(order[index]);
+            Vec3::new(column[0], column[1], column[2]).normalize_or_zero()
+        };
+
+        let (major, middle) = (axis(0), axis(1));
+        if !major.is_normalized() || !middle.is_normalized() || major.dot(middle).abs() > 1e-3 {
+            return (Vec3::X, Vec3::Y, Vec3::Z);
+        }
+        (major, middle, major.cross(middle))
+    }
+
+    /// The oriented bounding box of the points, aligned to their principal axes, as a
+    /// transform for the box centre and rotation in the cloud's local space and the box's
+    /// half-extents along those axes. Flat or collinear clouds get zero extents along the
+    /// missing axes. Returns `None` for an empty cloud.
+    pub fn obb(&self) -> Option<(Transform, Vec3)> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let (major, middle, minor) = self.principal_axes();
+        let basis = Mat3::from_cols(major, middle, minor);
+        // Fall back to the world axes if the decomposition didn't give a usable basis.
+        let basis = if (basis.determinant() - 1.0).abs() < 1e-3 {
+            basis
+        } else {
+            Mat3::IDENTITY
+        };
+
+        let local_from_box = basis.transpose();
+        let (min, max) = self.points.iter()
+            .map(|point| local_from_box * point.truncate())
+            .fold((Vec3::MAX, Vec3::MIN), |(min, max), point| (min.min(point), max.max(point)));
+
+        let transform = Transform::from_translation(basis * ((min + max) * 0.5))
+            .with_rotation(Quat::from_mat3(&basis));
+        Some((transform, (max - min) * 0.5))
+    }
+
+    /// Builds a `PointList` mesh of the point positions, for drawing with Bevy's standard
+    /// pipelines instead of the point cloud pass. Point sizes are dropped.
+    pub fn to_point_mesh(&self) -> Mesh {
+        let positions = self.points.iter()
+            .map(|point| point.truncate().to_array())
+            .collect::<Vec<_>>();
+        Mesh::new(PrimitiveTopology::PointList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(points) = Arc::get_mut(&mut self.points) {
+            points.clear();
+        } else {
+            self.points = Arc::default();
+        }
+    }
+}
+
+/// A value per point, parallel to `PointCloud::points`, uploaded alongside the points for
+/// materials to read as `VertexOutput::attribute`. Points past the end of `values` read zero.
+///
+/// A cloud with an attribute gets its own space in the point buffer, rather than sharing it with
+/// other clouds that use the same points.
+#[derive(Clone, Debug, Default, Reflect, Component)]
+#[reflect(Component)]
+pub struct PointCloudAttribute {
+    pub values: Arc<Vec<f32>>,
+}
+
+/// Per-point flags, parallel to `PointCloud::points`, marking points to draw in
+/// `PointCloudSettings::selection_color` whatever their material. Points past the end of
+/// `selected` aren't selected.
+///
+/// Like `PointCloudAttribute`, a selection gives the cloud its own space in the point buffer.
+#[derive(Clone, Debug, Default, Reflect, Component)]
+#[reflect(Component)]
+pub struct PointCloudSelection {
+    pub selected: Arc<Vec<bool>>,
+}
+
+#[derive(Clone, Debug, Resource, ExtractResource, Reflect)]
+#[reflect(Resource)]
+pub struct PointCloudSettings {
+    /// Opacity multiplied into every point cloud fragment.
+    pub global_alpha: f32,
+    /// Points closer to the camera than this fade out towards zero alpha. Zero disables the fade.
+    pub near_fade_distance: f32,
+    /// Points further in front of the camera than this aren't drawn. Zero disables the cull.
+    /// Points behind the camera are never drawn.
+    pub far_cull_distance: f32,
+    /// The colour of points flagged in a `PointCloudSelection`.
+    pub selection_color: Color,
+    /// A warning is logged when a point cloud grows past this many points.
+    pub max_points: usize,
+    /// Stop adding points to clouds that have reached `max_points`.
+    pub cap_at_max_points: bool,
+}
+
+impl Default for PointCloudSettings {
+    fn default() -> Self {
+        PointCloudSettings {
+            global_alpha: 1.0,
+            near_fade_distance: 0.0,
+            far_cull_distance: 0.0,
+            selection_color: Color::srgb(1.0, 1.0, 0.2),
+            max_points: DEFAULT_MAX_POINTS,
+            cap_at_max_points: false,
+        }
+    }
+}
+
+impl PointCloudSettings {
+    /// The limit to pass to `PointCloud::extend`, if clouds are capped.
+    pub fn point_limit(&self) -> Option<usize> {
+        self.cap_at_max_points.then_some(self.max_points)
+    }
+}
+
+/// The order to draw `points` in for `TransparencyMode::Sorted`: their indices from back to front
+/// as seen from the local-space `eye`, or in their own order without one.
+pub fn point_cloud_draw_order(points: &[Vec4], eye: Option<Vec3>) -> Vec<u32> {
+    let mut order = (0..points.len() as u32).collect::<Vec<_>>();
+    if let Some(eye) = eye {
+        let distance = |index: &u32| points[*index as usize].truncate().distance_squared(eye);
+        order.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    }
+    order
+}
+
+pub fn warn_point_cloud_limit(
+    settings: Res<PointCloudSettings>,
+    point_clouds: Query<(Entity, &PointCloud), Changed<PointCloud>>,
+    mut warned: Local<EntityHashSet>,
+) {
+    for (entity, point_cloud) in &point_clouds {
+        let len = point_cloud.points.len();
+        if len <= settings.max_points {
+            warned.remove(&entity);
+        } else if warned.insert(entity) {
+            if settings.cap_at_max_points {
+                warn!("Point cloud {:?} has {} points, more than the limit of {}; new points will be dropped", entity, len, settings.max_points);
+            } else {
+                warn!("Point cloud {:?} has {} points, more than the limit of {}", entity, len, settings.max_points);
+            }
+        }
+    }
+}
+
+/// Records each cloud's `GlobalTransform` before this frame's transform updates, so that the
+/// render world sees last frame's transform as the previous one.
+///
+/// Bevy only maintains `PreviousGlobalTransform` for meshes, so clouds would otherwise report no
+/// motion.
+pub fn update_point_cloud_previous_global_transforms(
+    mut commands: Commands,
+    mut point_clouds: Query<(Entity, &GlobalTransform, Option<&mut PreviousGlobalTransform>), With<PointCloud>>,
+) {
+    for (entity, transform, previous_transform) in &mut point_clouds {
+        match previous_transform {
+            Some(mut previous_transform) => previous_transform.0 = transform.affine(),
+            None => {
+                commands.entity(entity).insert(PreviousGlobalTransform(transform.affine()));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+pub struct PointCloudSettingsUniform {
+    pub global_alpha: f32,
+    pub near_fade_distance: f32,
+    pub far_cull_distance: f32,
+    pub selection_color: Vec4,
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct PointCloudSettingsBuffer(UniformBuffer<PointCloudSettingsUniform>);
+
+pub fn prepare_point_cloud_settings(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    settings: Res<PointCloudSettings>,
+    mut settings_buffer: ResMut<PointCloudSettingsBuffer>,
+) {
+    settings_buffer.set(PointCloudSettingsUniform {
+        global_alpha: settings.global_alpha.clamp(0.0, 1.0),
+        near_fade_distance: settings.near_fade_distance.max(0.0),
+        far_cull_distance: settings.far_cull_distance.max(0.0),
+        selection_color: settings.selection_color.to_linear().to_vec4(),
+    });
+    settings_buffer.write_buffer(&render_device, &render_queue);
+}
+
 pub struct PointCloudInstance {
     pub world_from_local: Affine3,
     pub previous_world_from_local: Affine3,
     pub num_points: u32,
-    pub allocation: Option<Allocation>,
+    pub allocation: Option<PointAllocation>,
+    /// Whether the points were uploaded with a `PointCloudAttribute`.
+    pub attribute: bool,
+    /// Whether the points were uploaded with a `PointCloudSelection`.
+    pub selection: bool,
+    /// Whether the points were uploaded for `TransparencyMode::Sorted`.
+    pub sorted: bool,
+    /// The local-space eye the draw order was last sorted from.
+    pub sorted_eye: Option<Vec3>,
 }
 
-#[derive(Clone, ShaderType)]
+#[derive(Clone, Default, ShaderType)]
 pub struct PointCloudUniform {
     pub world_from_local: [Vec4; 3],
     pub previous_world_from_local: [Vec4; 3],
+    /// Index of this cloud's first point in its point buffer chunk.
+    pub first_point: u32,
+    pub num_points: u32,
+}
+
+impl From<&PointCloudInstance> for PointCloudUniform {
+    fn from(instance: &PointCloudInstance) -> Self {
+        PointCloudUniform {
+            world_from_local: instance.world_from_local.to_transpose(),
+            previous_world_from_local: instance.previous_world_from_local.to_transpose(),
+            first_point: instance.allocation.as_ref().map_or(0, |allocation| allocation.allocation.offset),
+            num_points: instance.num_points,
+        }
+    }
+}
+
+struct SharedAllocation {
+    // Held so the Arc's address can't be reused, and so the main world's `Arc::make_mut` moves
+    // the points to a new address rather than changing them in place.
+    _points: Weak<Vec<Vec4>>,
+    points_key: usize,
+    refs: u32,
 }
 
+/// A range of points within one chunk of `PointCloudBuffers`.
+#[derive(Clone, Copy, Debug)]
+pub struct PointAllocation {
+    pub chunk: u32,
+    pub allocation: Allocation,
+}
+
+/// Tracks which ranges of each point buffer chunk are in use, sharing a range between clouds
+/// that upload the same `Arc`. Kept apart from the GPU buffers so it works without a device.
+pub struct PointAllocator {
+    pub chunks: Vec<Allocator>,
+    pub chunk_capacity: u32,
+    shared: HashMap<(u32, u32), SharedAllocation>,
+    shared_offsets: HashMap<usize, PointAllocation>,
+}
+
+impl PointAllocator {
+    pub fn new(chunk_count: u32, chunk_capacity: u32) -> PointAllocator {
+        PointAllocator {
+            chunks: (0..chunk_count).map(|_| Allocator::new(chunk_capacity)).collect(),
+            chunk_capacity,
+            shared: HashMap::default(),
+            shared_offsets: HashMap::default(),
+        }
+    }
+
+    pub fn allocate(&mut self, len: u32) -> PointAllocation {
+        let (chunk, allocation) = self.chunks.iter_mut()
+            .enumerate()
+            .find_map(|(index, allocator)| {
+                allocator.allocate(len)
+                    .map(|allocation| (index, allocation))
+            })
+            .expect("failed to allocate point buffer");
+        PointAllocation {
+            chunk: chunk as u32,
+            allocation,
+        }
+    }
+
+    pub fn free(&mut self, allocation: PointAllocation) {
+        self.chunks[allocation.chunk as usize].free(allocation.allocation);
+    }
+
+    /// Returns an allocation for `points`, sharing it with any other cloud that acquired the
+    /// same `Arc`, and whether it is new and so needs uploading. Release it with `release`.
+    pub fn acquire(&mut self, points: &Arc<Vec<Vec4>>, len: u32) -> (PointAllocation, bool) {
+        let points_key = Arc::as_ptr(points) as usize;
+        if let Some(allocation) = self.shared_offsets.get(&points_key).copied() {
+            if let Some(shared) = self.shared.get_mut(&(allocation.chunk, allocation.allocation.offset)) {
+                shared.refs += 1;
+                return (allocation, false);
+            }
+        }
+
+        let allocation = self.allocate(len);
+        self.shared.insert((allocation.chunk, allocation.allocation.offset), SharedAllocation {
+            _points: Arc::downgrade(points),
+            points_key,
+            refs: 1,
+        });
+        self.shared_offsets.insert(points_key, allocation);
+        (allocation, true)
+    }
+
+    pub fn release(&mut self, allocation: PointAllocation) {
+        let shared_key = (allocation.chunk, allocation.allocation.offset);
+        let Some(shared) = self.shared.get_mut(&shared_key) else {
+            self.free(allocation);
+            return;
+        };
+
+        shared.refs -= 1;
+        if shared.refs == 0 {
+            let points_key = shared.points_key;
+            self.shared.remove(&shared_key);
+            self.shared_offsets.remove(&points_key);
+            self.free(allocation);
+        }
+    }
+
+    /// Free points across all chunks.
+    pub fn total_free_space(&self) -> u32 {
+        self.chunks.iter()
+            .map(|allocator| allocator.storage_report().total_free_space)
+            .sum()
+    }
+}
+
+/// Storage for the points of every visible cloud.
+///
+/// Points are split across several buffers so that none exceeds the device's buffer size
+/// limits. Each cloud lives entirely in one chunk, so a cloud is limited to `chunk_capacity`
+/// points.
 #[derive(Resource)]
 pub struct PointCloudBuffers {
-    pub point_buffer: Buffer,
-    pub allocator: Allocator,
+    /// One buffer per chunk of `allocator`.
+    pub buffers: Vec<Buffer>,
+    /// The `PointCloudAttribute` values, one buffer per chunk, at the same offsets as the points.
+    pub attribute_buffers: Vec<Buffer>,
+    /// The `PointCloudSelection` flags, one `u32` per point, laid out like `attribute_buffers`.
+    pub selection_buffers: Vec<Buffer>,
+    /// For `TransparencyMode::Sorted`, the index within the chunk of the point to draw in each
+    /// slot, laid out like `attribute_buffers`.
+    pub order_buffers: Vec<Buffer>,
+    pub allocator: PointAllocator,
+    /// Points per chunk.
+    pub chunk_capacity: u32,
+    /// Points across all chunks.
+    pub capacity: u32,
 }
 
 impl PointCloudBuffers {
@@ -61,35 +499,196 @@ impl PointCloudBuffers {
     }
 
     pub fn with_capacity(render_device: &RenderDevice, capacity: u32) -> PointCloudBuffers {
-        let point_buffer = render_device.create_buffer(&BufferDescriptor {
-            label: Some("point cloud buffer"),
-            size: capacity as BufferAddress * size_of::<Vec4>() as BufferAddress,
-            usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST | BufferUsages::STORAGE,
-            mapped_at_creation: false,
-        });
-        let allocator = Allocator::new(capacity);
+        let limits = render_device.limits();
+        let max_chunk_size = (limits.max_storage_buffer_binding_size as BufferAddress)
+            .min(limits.max_buffer_size);
+        let chunk_capacity = ((max_chunk_size / size_of::<Vec4>() as BufferAddress) as u32)
+            .min(capacity)
+            .max(1);
+        let chunk_count = capacity.div_ceil(chunk_capacity).max(1);
+
+        let buffers = (0..chunk_count)
+            .map(|_| render_device.create_buffer(&BufferDescriptor {
+                label: Some("point cloud buffer"),
+                size: chunk_capacity as BufferAddress * size_of::<Vec4>() as BufferAddress,
+                usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }))
+            .collect::<Vec<_>>();
+        let attribute_buffers = (0..chunk_count)
+            .map(|_| render_device.create_buffer(&BufferDescriptor {
+                label: Some("point cloud attribute buffer"),
+                size: chunk_capacity as BufferAddress * size_of::<f32>() as BufferAddress,
+                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }))
+            .collect::<Vec<_>>();
+        let selection_buffers = (0..chunk_count)
+            .map(|_| render_device.create_buffer(&BufferDescriptor {
+                label: Some("point cloud selection buffer"),
+                size: chunk_capacity as BufferAddress * size_of::<u32>() as BufferAddress,
+                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }))
+            .collect::<Vec<_>>();
+        let order_buffers = (0..chunk_count)
+            .map(|_| render_device.create_buffer(&BufferDescriptor {
+                label: Some("point cloud order buffer"),
+                size: chunk_capacity as BufferAddress * size_of::<u32>() as BufferAddress,
+                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }))
+            .collect::<Vec<_>>();
         PointCloudBuffers {
-            point_buffer,
-            allocator,
+            capacity: chunk_capacity * chunk_count,
+            buffers,
+            attribute_buffers,
+            selection_buffers,
+            order_buffers,
+            allocator: PointAllocator::new(chunk_count, chunk_capacity),
+            chunk_capacity,
         }
     }
 
-    pub fn allocate(
+    /// Returns an allocation holding `points` with their `attribute` values and `selection`
+    /// flags, sharing it with any other cloud that uploaded the same `Arc` without either and
+    /// that isn't `sorted`. Release it with `release`.
+    pub fn acquire(
         &mut self,
         _render_device: &RenderDevice,
         render_queue: &RenderQueue,
-        points: &[Vec4],
-    ) -> Allocation {
-        let allocation = self.allocator.allocate(points.len() as u32)
-            .expect("failed to allocate point buffer");
-        let offset = allocation.offset as BufferAddress
-            * size_of::<Vec4>() as BufferAddress;
-        render_queue.write_buffer(&self.point_buffer, offset, bytemuck::cast_slice(points));
+        points: &Arc<Vec<Vec4>>,
+        attribute: Option<&[f32]>,
+        selection: Option<&[bool]>,
+        sorted: bool,
+    ) -> PointAllocation {
+        if points.len() > self.chunk_capacity as usize {
+            warn_once!("Point cloud has {} points but a point buffer chunk only holds {}; the rest won't be drawn", points.len(), self.chunk_capacity);
+        }
+        let len = points.len().min(self.chunk_capacity as usize);
+
+        let (allocation, is_new) = if attribute.is_some() || selection.is_some() || sorted {
+            // The values and draw order belong to one cloud, so its space can't be shared.
+            (self.allocator.allocate(len as u32), true)
+        } else {
+            self.allocator.acquire(points, len as u32)
+        };
+        if is_new {
+            let chunk = allocation.chunk as usize;
+            let offset = allocation.allocation.offset as BufferAddress;
+            render_queue.write_buffer(&self.buffers[chunk], offset * size_of::<Vec4>() as BufferAddress, bytemuck::cast_slice(&points[..len]));
+
+            // Clear the values too, so clouds without an attribute don't read a previous cloud's.
+            let mut values = attribute.map_or_else(Vec::new, |values| values[..values.len().min(len)].to_vec());
+            values.resize(len, 0.0);
+            render_queue.write_buffer(&self.attribute_buffers[chunk], offset * size_of::<f32>() as BufferAddress, bytemuck::cast_slice(&values));
+
+            // Only selected clouds read their flags, so the rest don't need clearing.
+            if let Some(selection) = selection {
+                self.write_selection(render_queue, &allocation, len as u32, selection);
+            }
+        }
         allocation
     }
 
-    pub fn free(&mut self, allocation: Allocation) {
-        self.allocator.free(allocation);
+    /// Writes the flags for the first `len` points of a selected cloud in place, without
+    /// touching its points.
+    pub fn write_selection(&self, render_queue: &RenderQueue, allocation: &PointAllocation, len: u32, selection: &[bool]) {
+        let flags = (0..len)
+            .map(|index| selection.get(index as usize).copied().unwrap_or(false) as u32)
+            .collect::<Vec<_>>();
+        let offset = allocation.allocation.offset as BufferAddress;
+        render_queue.write_buffer(&self.selection_buffers[allocation.chunk as usize], offset * size_of::<u32>() as BufferAddress, bytemuck::cast_slice(&flags));
+    }
+
+    pub fn release(&mut self, allocation: PointAllocation) {
+        self.allocator.release(allocation);
+    }
+
+    /// Writes the draw `order` of a sorted cloud's points, as indices into its allocation.
+    pub fn write_order(&self, render_queue: &RenderQueue, allocation: &PointAllocation, order: &[u32]) {
+        let offset = allocation.allocation.offset;
+        let indices = order.iter()
+            .map(|index| offset + index)
+            .collect::<Vec<_>>();
+        render_queue.write_buffer(&self.order_buffers[allocation.chunk as usize], offset as BufferAddress * size_of::<u32>() as BufferAddress, bytemuck::cast_slice(&indices));
+    }
+
+    pub fn read_back(
+        &self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        allocation: &PointAllocation,
+        len: u32,
+    ) -> impl Future<Output = Vec<Vec4>> {
+        #[derive(Default)]
+        struct ReadBackState {
+            mapped: Option<bool>,
+            waker: Option<Waker>,
+        }
+
+        let stride = size_of::<Vec4>() as BufferAddress;
+        let size = len as BufferAddress * stride;
+        let state = Arc::new(Mutex::new(ReadBackState::default()));
+        let staging_buffer = (len > 0).then(|| {
+            let staging_buffer = render_device.create_buffer(&BufferDescriptor {
+                label: Some("point cloud read back buffer"),
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("point cloud read back"),
+            });
+            encoder.copy_buffer_to_buffer(
+                &self.buffers[allocation.chunk as usize],
+                allocation.allocation.offset as BufferAddress * stride,
+                &staging_buffer,
+                0,
+                size,
+            );
+            render_queue.submit([encoder.finish()]);
+
+            let state = state.clone();
+            staging_buffer.slice(..).map_async(MapMode::Read, move |result| {
+                let mut state = state.lock().unwrap();
+                state.mapped = Some(result.is_ok());
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+            staging_buffer
+        });
+
+        let render_device = render_device.clone();
+        async move {
+            let Some(staging_buffer) = staging_buffer else {
+                return Vec::new();
+            };
+
+            let mapped = poll_fn(|cx| {
+                render_device.poll(Maintain::Poll);
+                let mut state = state.lock().unwrap();
+                match state.mapped {
+                    Some(mapped) => Poll::Ready(mapped),
+                    None => {
+                        state.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            }).await;
+            if !mapped {
+                return Vec::new();
+            }
+
+            let points = {
+                let view = staging_buffer.slice(..).get_mapped_range();
+                bytemuck::cast_slice::<u8, Vec4>(&view).to_vec()
+            };
+            staging_buffer.unmap();
+            points
+        }
     }
 }
 
@@ -103,12 +702,48 @@ impl FromWorld for PointCloudBuffers {
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct PointCloudInstances(EntityHashMap<PointCloudInstance>);
 
+/// A cloud whose points or attribute changed, to upload in `upload_point_clouds`.
+pub struct PendingPointCloud {
+    pub entity: Entity,
+    pub points: Arc<Vec<Vec4>>,
+    pub attribute: Option<Arc<Vec<f32>>>,
+    pub selection: Option<Arc<Vec<bool>>>,
+}
+
 #[derive(Default, Resource, Deref, DerefMut)]
-pub struct PendingPointClouds(Vec<(Entity, Arc<Vec<Vec4>>)>);
+pub struct PendingPointClouds(Vec<PendingPointCloud>);
 
+/// A cloud whose `PointCloudSelection` changed while its points didn't, to write in
+/// `write_point_cloud_selections`.
+pub struct PendingPointCloudSelection {
+    pub entity: Entity,
+    pub selected: Arc<Vec<bool>>,
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct PendingPointCloudSelections(Vec<PendingPointCloudSelection>);
+
+/// A sorted cloud to sort from a new local-space `eye` in `write_point_cloud_orders`.
+pub struct PendingPointCloudOrder {
+    pub entity: Entity,
+    pub points: Arc<Vec<Vec4>>,
+    pub eye: Option<Vec3>,
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct PendingPointCloudOrders(Vec<PendingPointCloudOrder>);
+
+/// Extracts visible clouds, queueing uploads for changed points. For `TransparencyMode::Sorted`
+/// it also queues a sort of each cloud's draw order whenever the first active 3D camera has
+/// moved far enough, leaving the points themselves in their own order.
 pub fn extract_point_clouds(
     mut point_cloud_instances: ResMut<PointCloudInstances>,
     mut pending_point_clouds: ResMut<PendingPointClouds>,
+    mut pending_point_cloud_orders: ResMut<PendingPointCloudOrders>,
+    mut pending_point_cloud_selections: ResMut<PendingPointCloudSelections>,
+    mut point_cloud_buffers: ResMut<PointCloudBuffers>,
+    transparency_settings: Extract<Option<Res<OrderIndependentTransparencySettings>>>,
+    cameras: Extract<Query<(&Camera, &GlobalTransform), With<Camera3d>>>,
     clouds_query: Extract<
         Query<(
             Entity,
@@ -116,37 +751,104 @@ pub fn extract_point_clouds(
             &GlobalTransform,
             Option<&PreviousGlobalTransform>,
             Ref<PointCloud>,
+            Option<Ref<PointCloudAttribute>>,
+            Option<Ref<PointCloudSelection>>,
         )>,
     >,
 ) {
-    point_cloud_instances.retain(|entity, _| clouds_query.contains(*entity));
-    for (entity, view_visibility, transform, previous_transform, point_cloud) in &clouds_query {
+    point_cloud_instances.retain(|entity, instance| {
+        if clouds_query.contains(*entity) {
+            return true;
+        }
+
+        if let Some(allocation) = instance.allocation.take() {
+            point_cloud_buffers.release(allocation);
+        }
+        false
+    });
+    let sorted = transparency_settings.as_ref().is_some_and(|settings| settings.mode == TransparencyMode::Sorted);
+    let eye = cameras.iter()
+        .find(|(camera, _)| camera.is_active)
+        .map(|(_, transform)| transform.translation());
+    for (entity, view_visibility, transform, previous_transform, point_cloud, attribute, selection) in &clouds_query {
         if !view_visibility.get() {
-            point_cloud_instances.remove(&entity);
+            if let Some(allocation) = point_cloud_instances.remove(&entity)
+                .and_then(|instance| instance.allocation) {
+                point_cloud_buffers.release(allocation);
+            }
             continue;
         }
         let transform = transform.affine();
         let previous_transform = previous_transform.map(|t| t.0).unwrap_or(transform);
-        let is_new = if let Some(existing) = point_cloud_instances.get_mut(&entity) {
+        // Clouds too big for a chunk are uploaded truncated.
+        let num_points = (point_cloud.points.len() as u32).min(point_cloud_buffers.chunk_capacity);
+        let has_attribute = attribute.is_some();
+        let has_selection = selection.is_some();
+        let needs_upload = if let Some(existing) = point_cloud_instances.get_mut(&entity) {
             existing.world_from_local = (&transform).into();
             existing.previous_world_from_local = (&previous_transform).into();
-            existing.num_points = point_cloud.points.len() as u32;
-            false
+            existing.num_points = num_points;
+            // Re-upload when the attribute or selection is added or removed, or the mode changes.
+            let toggled = existing.attribute != has_attribute
+                || existing.selection != has_selection
+                || existing.sorted != sorted;
+            existing.attribute = has_attribute;
+            existing.selection = has_selection;
+            existing.sorted = sorted;
+            toggled
         } else {
             point_cloud_instances.insert(
                 entity,
                 PointCloudInstance {
                     world_from_local: (&transform).into(),
                     previous_world_from_local: (&previous_transform).into(),
-                    num_points: point_cloud.points.len() as u32,
+                    num_points,
                     allocation: None,
+                    attribute: has_attribute,
+                    selection: has_selection,
+                    sorted,
+                    sorted_eye: None,
                 },
             );
             true
         };
 
-        if is_new || point_cloud.is_changed() {
-            pending_point_clouds.push((entity, point_cloud.points.clone()));
+        let attribute_changed = attribute.as_ref().is_some_and(|attribute| attribute.is_changed());
+        let upload = needs_upload || point_cloud.is_changed() || attribute_changed;
+        if upload {
+            pending_point_clouds.push(PendingPointCloud {
+                entity,
+                points: point_cloud.points.clone(),
+                attribute: attribute.map(|attribute| attribute.values.clone()),
+                selection: selection.map(|selection| selection.selected.clone()),
+            });
+        } else if let Some(selection) = selection.filter(|selection| selection.is_changed()) {
+            // The cloud already has its own space, so only the flags need writing.
+            pending_point_cloud_selections.push(PendingPointCloudSelection {
+                entity,
+                selected: selection.selected.clone(),
+            });
+        }
+
+        if !sorted {
+            continue;
+        }
+        // A fresh upload needs an order even when nothing has moved.
+        let local_eye = eye.map(|eye| transform.inverse().transform_point3(eye));
+        let Some(instance) = point_cloud_instances.get_mut(&entity) else {
+            continue;
+        };
+        let moved = match (instance.sorted_eye, local_eye) {
+            (Some(sorted_eye), Some(eye)) => sorted_eye.distance(eye) > DRAW_ORDER_RESORT_DISTANCE,
+            (sorted_eye, eye) => sorted_eye.is_some() != eye.is_some(),
+        };
+        if upload || moved {
+            instance.sorted_eye = local_eye;
+            pending_point_cloud_orders.push(PendingPointCloudOrder {
+                entity,
+                points: point_cloud.points.clone(),
+                eye: local_eye,
+            });
         }
     }
 }
@@ -158,16 +860,60 @@ pub fn upload_point_clouds(
     mut pending_point_clouds: ResMut<PendingPointClouds>,
     mut point_cloud_buffers: ResMut<PointCloudBuffers>,
 ) {
-    for (entity, points) in pending_point_clouds.drain(..) {
-        let Some(point_cloud) = point_clouds.get_mut(&entity) else {
+    for pending in pending_point_clouds.drain(..) {
+        let Some(point_cloud) = point_clouds.get_mut(&pending.entity) else {
             continue;
         };
 
         if let Some(allocation) = point_cloud.allocation.take() {
-            point_cloud_buffers.free(allocation);
+            point_cloud_buffers.release(allocation);
+        }
+
+        if pending.points.is_empty() {
+            continue;
         }
 
-        point_cloud.allocation = Some(point_cloud_buffers.allocate(&render_device, &render_queue, &points));
+        let attribute = pending.attribute.as_deref().map(Vec::as_slice);
+        let selection = pending.selection.as_deref().map(Vec::as_slice);
+        point_cloud.allocation = Some(point_cloud_buffers.acquire(&render_device, &render_queue, &pending.points, attribute, selection, point_cloud.sorted));
+    }
+}
+
+pub fn write_point_cloud_selections(
+    render_queue: Res<RenderQueue>,
+    point_clouds: Res<PointCloudInstances>,
+    mut pending_point_cloud_selections: ResMut<PendingPointCloudSelections>,
+    point_cloud_buffers: Res<PointCloudBuffers>,
+) {
+    for pending in pending_point_cloud_selections.drain(..) {
+        let Some(point_cloud) = point_clouds.get(&pending.entity) else {
+            continue;
+        };
+        let Some(allocation) = point_cloud.allocation.as_ref() else {
+            continue;
+        };
+        point_cloud_buffers.write_selection(&render_queue, allocation, point_cloud.num_points, &pending.selected);
+    }
+}
+
+/// Sorts the draw orders queued by `extract_point_clouds` and writes them, in the render world so
+/// that the sort doesn't hold up extraction.
+pub fn write_point_cloud_orders(
+    render_queue: Res<RenderQueue>,
+    point_clouds: Res<PointCloudInstances>,
+    mut pending_point_cloud_orders: ResMut<PendingPointCloudOrders>,
+    point_cloud_buffers: Res<PointCloudBuffers>,
+) {
+    for pending in pending_point_cloud_orders.drain(..) {
+        let Some(point_cloud) = point_clouds.get(&pending.entity) else {
+            continue;
+        };
+        let Some(allocation) = point_cloud.allocation.as_ref() else {
+            continue;
+        };
+        let len = pending.points.len().min(point_cloud.num_points as usize);
+        let order = point_cloud_draw_order(&pending.points[..len], pending.eye);
+        point_cloud_buffers.write_order(&render_queue, allocation, &order);
     }
 }
 
@@ -175,6 +921,9 @@ pub fn upload_point_clouds(
 pub struct PointCloudPipelineKey {
     msaa_samples: u32,
     view_key: MeshPipelineViewLayoutKey,
+    mode: TransparencyMode,
+    /// Whether the cloud has a `PointCloudSelection`.
+    selection: bool,
 }
 
 #[derive(Clone, Resource)]
@@ -197,6 +946,10 @@ impl FromWorld for PointCloudPipeline {
                 (
                     GpuArrayBuffer::<PointCloudUniform>::binding_layout(render_device),
                     storage_buffer_read_only::<Vec4>(false),
+                    uniform_buffer::<PointCloudSettingsUniform>(false),
+                    storage_buffer_read_only::<f32>(false),
+                    storage_buffer_read_only::<u32>(false),
+                    storage_buffer_read_only::<u32>(false),
                 ),
             ),
         );
@@ -231,7 +984,19 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
             dst_factor: BlendFactor::OneMinusSrcAlpha,
             operation: BlendOperation::Add,
         };
-        let shader_defs = vec![];
+        // Sorted points are drawn back to front, so plain premultiplied "over" blending is exact.
+        let blend_over = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            operation: BlendOperation::Add,
+        };
+        let (blend_colour, mut shader_defs) = match key.mode {
+            TransparencyMode::WeightedBlended => (blend_add, vec![]),
+            TransparencyMode::Sorted => (blend_over, vec!["SORTED_TRANSPARENCY".into()]),
+        };
+        if key.selection {
+            shader_defs.push("POINT_SELECTION".into());
+        }
         RenderPipelineDescriptor {
             vertex: VertexState {
                 shader: self.shader.clone(),
@@ -247,8 +1012,8 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
                     Some(ColorTargetState {
                         format: TextureFormat::Rgba16Float,
                         blend: Some(BlendState {
-                            color: blend_add,
-                            alpha: blend_add,
+                            color: blend_colour,
+                            alpha: blend_colour,
                         }),
                         write_mask: ColorWrites::ALL,
                     }),
@@ -291,15 +1056,14 @@ impl GetBatchData for PointCloudPipeline {
         (ref point_cloud_instances, ref mut indirect): &mut SystemParamItem<Self::Param>,
         entity: Entity,
     ) -> Option<(Self::BufferData, Option<Self::CompareData>)> {
-        let instance = point_cloud_instances.get(&entity)?;
+        let Some(instance) = point_cloud_instances.get(&entity) else {
+            // Another drawable queued into the shared phase. Give it an empty draw so the
+            // instance and indirect buffers stay in step.
+            indirect.push_empty();
+            return Some((PointCloudUniform::default(), None));
+        };
         indirect.push(instance);
-        Some((
-            PointCloudUniform {
-                world_from_local: instance.world_from_local.to_transpose(),
-                previous_world_from_local: instance.previous_world_from_local.to_transpose(),
-            },
-            Some(())
-        ))
+        Some((instance.into(), Some(())))
     }
 }
 
@@ -310,12 +1074,12 @@ impl GetFullBatchData for PointCloudPipeline {
         (point_cloud_instances, ref mut indirect): &mut SystemParamItem<Self::Param>,
         entity: Entity,
     ) -> Option<Self::BufferData> {
-        let instance = point_cloud_instances.get(&entity)?;
+        let Some(instance) = point_cloud_instances.get(&entity) else {
+            indirect.push_empty();
+            return Some(PointCloudUniform::default());
+        };
         indirect.push(instance);
-        Some(PointCloudUniform {
-            world_from_local: instance.world_from_local.to_transpose(),
-            previous_world_from_local: instance.previous_world_from_local.to_transpose(),
-        })
+        Some(instance.into())
     }
 
     fn get_index_and_compare_data(
@@ -342,9 +1106,10 @@ impl GetFullBatchData for PointCloudPipeline {
     }
 }
 
+/// One bind group per point buffer chunk.
 #[derive(Resource)]
 pub struct PointCloudBindGroup {
-    pub value: BindGroup,
+    pub values: Vec<BindGroup>,
 }
 
 pub fn write_point_cloud_indirect(
@@ -362,38 +1127,61 @@ pub fn prepare_point_cloud_bind_group(
     render_device: Res<RenderDevice>,
     point_cloud_uniforms: Res<BatchedInstanceBuffer<PointCloudUniform>>,
     point_cloud_buffers: Res<PointCloudBuffers>,
+    settings_buffer: Res<PointCloudSettingsBuffer>,
 ) {
     let Some(point_cloud_uniform) = point_cloud_uniforms.binding() else {
         return;
     };
+    let Some(settings) = settings_buffer.binding() else {
+        return;
+    };
 
     commands.insert_resource(PointCloudBindGroup {
-        value: render_device.create_bind_group(
-            "point_cloud_bind_group",
-            &point_cloud_pipeline.point_cloud_layout,
-            &BindGroupEntries::sequential((
-                point_cloud_uniform,
-                point_cloud_buffers.point_buffer.as_entire_binding(),
-            )),
-        ),
+        values: point_cloud_buffers.buffers.iter()
+            .zip(&point_cloud_buffers.attribute_buffers)
+            .zip(&point_cloud_buffers.selection_buffers)
+            .zip(&point_cloud_buffers.order_buffers)
+            .map(|(((buffer, attribute_buffer), selection_buffer), order_buffer)| render_device.create_bind_group(
+                "point_cloud_bind_group",
+                &point_cloud_pipeline.point_cloud_layout,
+                &BindGroupEntries::sequential((
+                    point_cloud_uniform.clone(),
+                    buffer.as_entire_binding(),
+                    settings.clone(),
+                    attribute_buffer.as_entire_binding(),
+                    selection_buffer.as_entire_binding(),
+                    order_buffer.as_entire_binding(),
+                )),
+            ))
+            .collect(),
     });
 }
 
 pub struct SetPointCloudBindGroup<const I: usize>;
 
 impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetPointCloudBindGroup<I> {
-    type Param = SRes<PointCloudBindGroup>;
+    type Param = (
+        SRes<PointCloudBindGroup>,
+        SRes<PointCloudInstances>,
+    );
     type ViewQuery = ();
     type ItemQuery = ();
 
     fn render<'w>(
-        _item: &P,
+        item: &P,
         _view: (),
         _entity: Option<()>,
-        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        (bind_group, point_cloud_instances): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        pass.set_bind_group(I, &bind_group.into_inner().value, &[]);
+        // Batches never span chunks, since the chunk is part of the bin key.
+        let chunk = point_cloud_instances.get(&item.entity())
+            .and_then(|instance| instance.allocation.as_ref())
+            .map_or(0, |allocation| allocation.chunk);
+        let Some(bind_group) = bind_group.into_inner().values.get(chunk as usize) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
         RenderCommandResult::Success
     }
 }
@@ -401,7 +1189,7 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetPointCloudBindGroup<I
 struct DrawPointCloudMesh;
 
 impl<P: PhaseItem> RenderCommand<P> for DrawPointCloudMesh {
-    type Param = SRes<PointCloudIndirect>;
+    type Param = (SRes<PointCloudIndirect>, SRes<SharedPointCloudDrawStats>);
     type ViewQuery = ();
     type ItemQuery = ();
 
@@ -410,7 +1198,7 @@ impl<P: PhaseItem> RenderCommand<P> for DrawPointCloudMesh {
         item: &P,
         _view: QueryItem<'w, Self::ViewQuery>,
         _entity: Option<()>,
-        indirect: SystemParamItem<'w, '_, Self::Param>,
+        (indirect, draw_stats): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let Some(indirect_buffer) = indirect.into_inner().0.buffer() else {
@@ -420,23 +1208,54 @@ impl<P: PhaseItem> RenderCommand<P> for DrawPointCloudMesh {
         let range = item.batch_range();
         let indirect_offset = range.start as BufferAddress * size_of::<DrawIndirect>() as BufferAddress;
         pass.multi_draw_indirect(indirect_buffer, indirect_offset, range.len() as u32);
+        draw_stats.record(range.len() as u32);
         RenderCommandResult::Success
     }
 }
 
-pub fn extract_camera_phases(
-    mut transparent_phases: ResMut<ViewBinnedRenderPhases<OrderIndependentTransparent3d>>,
-    cameras: Extract<Query<(Entity, &Camera), With<Camera3d>>>,
-) {
-    for (entity, camera) in &cameras {
-        if !camera.is_active {
-            continue;
-        }
+/// Point cloud draws submitted in a frame, across all views.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PointCloudDrawStats {
+    /// Indirect draw calls, one per batch.
+    pub draw_calls: u32,
+    /// Instances drawn by those calls, one per cloud.
+    pub instances: u32,
+}
 
-        transparent_phases.insert_or_clear(entity);
+#[derive(Default)]
+struct PointCloudDrawCounters {
+    draw_calls: AtomicU32,
+    instances: AtomicU32,
+}
+
+/// Counted by the render world as it draws, and read by the main world.
+#[derive(Clone, Default, Resource)]
+pub struct SharedPointCloudDrawStats {
+    counters: Arc<PointCloudDrawCounters>,
+    last_frame: Arc<Mutex<PointCloudDrawStats>>,
+}
+
+impl SharedPointCloudDrawStats {
+    /// The counts from the last frame the render world finished.
+    pub fn get(&self) -> PointCloudDrawStats {
+        *self.last_frame.lock().unwrap()
+    }
+
+    fn record(&self, instances: u32) {
+        self.counters.draw_calls.fetch_add(1, Ordering::Relaxed);
+        self.counters.instances.fetch_add(instances, Ordering::Relaxed);
     }
 
-    transparent_phases.retain(|e, _| cameras.contains(*e));
+    fn finish_frame(&self) {
+        *self.last_frame.lock().unwrap() = PointCloudDrawStats {
+            draw_calls: self.counters.draw_calls.swap(0, Ordering::Relaxed),
+            instances: self.counters.instances.swap(0, Ordering::Relaxed),
+        };
+    }
+}
+
+pub fn finish_point_cloud_draw_stats(draw_stats: Res<SharedPointCloudDrawStats>) {
+    draw_stats.finish_frame();
 }
 
 #[repr(C)]
@@ -459,73 +1278,29 @@ impl Default for PointCloudIndirect {
 
 impl PointCloudIndirect {
     pub fn push(&mut self, instance: &PointCloudInstance) {
+        // first_instance indexes the batched PointCloudUniform buffer, which is written in the
+        // same order as this buffer. first_vertex offsets into the cloud's point buffer chunk.
         let first_instance = self.len() as u32;
+        let (vertex_count, first_vertex) = match instance.allocation.as_ref() {
+            Some(allocation) => (instance.num_points * 6, allocation.allocation.offset * 6),
+            None => (0, 0),
+        };
         self.0.push(DrawIndirect {
-            vertex_count: instance.num_points * 6,
+            vertex_count,
             instance_count: 1,
-            first_vertex: 0,
+            first_vertex,
             first_instance,
         });
     }
-}
-
-#[derive(Component)]
-pub struct TransparentAccumulationTexture {
-    pub color_attachment: ColorAttachment,
-    pub alpha_attachment: ColorAttachment,
-}
-
-pub fn prepare_transparent_accumulation_texture(
-    mut commands: Commands,
-    mut texture_cache: ResMut<TextureCache>,
-    msaa: Res<Msaa>,
-    render_device: Res<RenderDevice>,
-    views: Query<(Entity, &ExtractedCamera)>,
-) {
-    for (entity, camera) in &views {
-        let Some(physical_target_size) = camera.physical_target_size else {
-            continue;
-        };
-
-        let size = Extent3d {
-            depth_or_array_layers: 1,
-            width: physical_target_size.x,
-            height: physical_target_size.y,
-        };
-
-        let colour_texture = {
-            let descriptor = TextureDescriptor {
-                label: Some("transparency colour texture"),
-                size,
-                mip_level_count: 1,
-                sample_count: msaa.samples(),
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba16Float,
-                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
-                view_formats: &[TextureFormat::Rgba16Float],
-            };
 
-            texture_cache.get(&render_device, descriptor)
-        };
-
-        let alpha_texture = {
-            let descriptor = TextureDescriptor {
-                label: Some("transparency alpha texture"),
-                size,
-                mip_level_count: 1,
-                sample_count: msaa.samples(),
-                dimension: TextureDimension::D2,
-                format: TextureFormat::R16Float,
-                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
-                view_formats: &[TextureFormat::R16Float],
-            };
-
-            texture_cache.get(&render_device, descriptor)
-        };
-
-        commands.entity(entity).insert(TransparentAccumulationTexture {
-            color_attachment: ColorAttachment::new(colour_texture, None, Some(LinearRgba::NONE)),
-            alpha_attachment: ColorAttachment::new(alpha_texture, None, Some(LinearRgba::WHITE)),
+    /// Pushes a draw of nothing, for phase items that aren't point clouds.
+    pub fn push_empty(&mut self) {
+        let first_instance = self.len() as u32;
+        self.0.push(DrawIndirect {
+            vertex_count: 0,
+            instance_count: 1,
+            first_vertex: 0,
+            first_instance,
         });
     }
 }
@@ -534,22 +1309,34 @@ pub struct PointCloudPlugin;
 
 impl Plugin for PointCloudPlugin {
     fn build(&self, app: &mut App) {
+        let draw_stats = SharedPointCloudDrawStats::default();
         app
+            .register_type::<PointCloudSettings>()
+            .init_resource::<PointCloudSettings>()
+            .insert_resource(draw_stats.clone())
             .add_plugins((
                 BinnedRenderPhasePlugin::<OrderIndependentTransparent3d, PointCloudPipeline>::default(),
+                ExtractResourcePlugin::<PointCloudSettings>::default(),
+                offscreen::PointCloudRenderPlugin,
             ))
+            .add_systems(PreUpdate, update_point_cloud_previous_global_transforms)
+            .add_systems(Update, source::receive_point_cloud_sources)
             .add_systems(PostUpdate, (
                 check_visibility::<With<PointCloud>>.in_set(VisibilitySystems::CheckVisibility),
+                warn_point_cloud_limit,
             ));
         app.sub_app_mut(RenderApp)
+            .insert_resource(draw_stats)
             .init_resource::<SpecializedRenderPipelines<PointCloudPipeline>>()
             .add_systems(ExtractSchedule, (
                 extract_point_clouds,
-                extract_camera_phases,
             ))
             .add_systems(Render, (
-                upload_point_clouds.in_set(RenderSet::PrepareResources),
-                prepare_transparent_accumulation_texture.in_set(RenderSet::PrepareResources),
+                upload_point_clouds.in_set(RenderSet::PrepareAssets),
+                (write_point_cloud_selections, write_point_cloud_orders)
+                    .in_set(RenderSet::PrepareAssets)
+                    .after(upload_point_clouds),
+                prepare_point_cloud_settings.in_set(RenderSet::PrepareResources),
                 write_batched_instance_buffer::<PointCloudPipeline>
                     .in_set(RenderSet::PrepareResourcesFlush),
                 write_point_cloud_indirect.in_set(RenderSet::PrepareResourcesFlush),
@@ -557,6 +1344,7 @@ impl Plugin for PointCloudPlugin {
                 clear_batched_cpu_instance_buffers::<PointCloudPipeline>
                     .in_set(RenderSet::Cleanup)
                     .after(RenderSet::Render),
+                finish_point_cloud_draw_stats.in_set(RenderSet::Cleanup),
             ));
     }
 
@@ -570,7 +1358,208 @@ impl Plugin for PointCloudPlugin {
                 .init_resource::<PointCloudInstances>()
                 .init_resource::<PointCloudBuffers>()
                 .init_resource::<PointCloudIndirect>()
-                .init_resource::<PendingPointClouds>();
+                .init_resource::<PointCloudSettingsBuffer>()
+                .init_resource::<PendingPointClouds>()
+                .init_resource::<PendingPointCloudOrders>()
+                .init_resource::<PendingPointCloudSelections>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+
+    use super::*;
+
+    #[test]
+    fn releasing_clouds_returns_their_space() {
+        let mut allocator = PointAllocator::new(2, 1 << 16);
+        let initial_free = allocator.total_free_space();
+        for round in 0..10 {
+            let clouds = (0..50)
+                .map(|index| Arc::new(vec![Vec4::ZERO; 1 + (index * 37 + round) % 500]))
+                .collect::<Vec<_>>();
+            let allocations = clouds.iter()
+                .map(|points| allocator.acquire(points, points.len() as u32).0)
+                .collect::<Vec<_>>();
+            assert!(allocator.total_free_space() < initial_free);
+
+            for allocation in allocations {
+                allocator.release(allocation);
+            }
+            assert_eq!(allocator.total_free_space(), initial_free);
         }
     }
+
+    #[test]
+    fn shared_points_are_freed_with_their_last_cloud() {
+        let mut allocator = PointAllocator::new(1, 1 << 16);
+        let initial_free = allocator.total_free_space();
+        let points = Arc::new(vec![Vec4::ZERO; 100]);
+
+        let (first, first_is_new) = allocator.acquire(&points, 100);
+        let (second, second_is_new) = allocator.acquire(&points, 100);
+        assert!(first_is_new);
+        assert!(!second_is_new);
+        assert_eq!(first.allocation.offset, second.allocation.offset);
+
+        allocator.release(first);
+        assert!(allocator.total_free_space() < initial_free);
+        allocator.release(second);
+        assert_eq!(allocator.total_free_space(), initial_free);
+    }
+
+    #[test]
+    fn each_cloud_draws_with_its_own_transform_and_points() {
+        let transforms = [
+            Transform::from_xyz(-5., 0., 0.),
+            Transform::from_xyz(0., 2., 0.).with_rotation(Quat::from_rotation_y(1.)),
+            Transform::from_xyz(5., 0., -3.).with_scale(Vec3::splat(2.)),
+        ];
+        let mut allocator = PointAllocator::new(1, 1 << 16);
+        let mut indirect = PointCloudIndirect::default();
+        let mut uniforms = Vec::new();
+        for (index, transform) in transforms.iter().enumerate() {
+            let world_from_local = transform.compute_affine();
+            let instance = PointCloudInstance {
+                world_from_local: (&world_from_local).into(),
+                previous_world_from_local: (&world_from_local).into(),
+                num_points: 10 * (index as u32 + 1),
+                allocation: Some(allocator.allocate(10 * (index as u32 + 1))),
+                attribute: false,
+                selection: false,
+                sorted: false,
+                sorted_eye: None,
+            };
+            indirect.push(&instance);
+            uniforms.push((PointCloudUniform::from(&instance), instance.allocation.unwrap()));
+        }
+
+        let point = vec3(1., 2., 3.);
+        for (index, (draw, (uniform, allocation))) in indirect.values().iter().zip(&uniforms).enumerate() {
+            // Each draw's instance indexes its own cloud's uniform, and its vertices its points.
+            assert_eq!(draw.first_instance, index as u32);
+            assert_eq!(draw.first_vertex, allocation.allocation.offset * 6);
+            assert_eq!(draw.vertex_count, uniform.num_points * 6);
+            assert_eq!(uniform.first_point, allocation.allocation.offset);
+
+            let [x, y, z] = uniform.world_from_local;
+            let world = vec3(x.dot(point.extend(1.)), y.dot(point.extend(1.)), z.dot(point.extend(1.)));
+            assert!(world.distance(transforms[index].transform_point(point)) < 1e-4, "cloud {index} at {world}");
+        }
+    }
+
+    #[test]
+    fn extend_keeps_shared_points_when_nothing_is_added() {
+        let mut cloud = PointCloud { points: Arc::new(vec![Vec4::ONE; 4]) };
+        let snapshot = cloud.points.clone();
+
+        assert_eq!(cloud.extend(std::iter::empty(), None), 0);
+        assert_eq!(cloud.extend([Vec4::ZERO], Some(4)), 0);
+        assert!(Arc::ptr_eq(&cloud.points, &snapshot));
+
+        assert_eq!(cloud.extend([Vec4::ZERO; 3], Some(6)), 2);
+        assert_eq!(cloud.points.len(), 6);
+        assert_eq!(snapshot.len(), 4);
+    }
+
+    #[test]
+    fn local_density_counts_neighbours_within_the_radius() {
+        let cloud = PointCloud {
+            points: Arc::new(vec![
+                Vec4::new(0., 0., 0., 1.),
+                Vec4::new(0.1, 0., 0., 1.),
+                Vec4::new(10., 0., 0., 1.),
+            ]),
+        };
+        let volume = 4. / 3. * PI * 0.5f32.powi(3);
+        let densities = cloud.local_densities(0.5);
+        assert_eq!(densities.len(), 3);
+        assert!((densities[0] - 2. / volume).abs() < 1e-3);
+        assert!((densities[1] - 2. / volume).abs() < 1e-3);
+        assert!((densities[2] - 1. / volume).abs() < 1e-3);
+    }
+
+    #[test]
+    fn draw_order_is_back_to_front_without_moving_points() {
+        let points = [
+            Vec4::new(0., 0., 1., 1.),
+            Vec4::new(0., 0., 3., 1.),
+            Vec4::new(0., 0., 2., 1.),
+        ];
+        assert_eq!(point_cloud_draw_order(&points, Some(Vec3::ZERO)), vec![1, 2, 0]);
+        assert_eq!(point_cloud_draw_order(&points, Some(Vec3::new(0., 0., 4.))), vec![0, 2, 1]);
+        assert_eq!(point_cloud_draw_order(&points, None), vec![0, 1, 2]);
+    }
+
+    fn box_corners(half_extents: Vec3, transform: Transform) -> PointCloud {
+        let points = (0..8)
+            .map(|corner| {
+                let sign = Vec3::new(
+                    if corner & 1 == 0 { -1. } else { 1. },
+                    if corner & 2 == 0 { -1. } else { 1. },
+                    if corner & 4 == 0 { -1. } else { 1. },
+                );
+                transform.transform_point(sign * half_extents).extend(1.)
+            })
+            .collect();
+        PointCloud { points: Arc::new(points) }
+    }
+
+    fn assert_finite_axes(cloud: &PointCloud) {
+        let (major, middle, minor) = cloud.principal_axes();
+        for axis in [major, middle, minor] {
+            assert!(axis.is_finite() && axis.is_normalized(), "axis {axis}");
+        }
+    }
+
+    #[test]
+    fn obb_of_an_axis_aligned_box_matches_its_extents() {
+        let cloud = box_corners(vec3(2., 1., 0.5), Transform::from_xyz(1., 2., 3.));
+        let (major, middle, minor) = cloud.principal_axes();
+        assert!(major.abs().abs_diff_eq(Vec3::X, 1e-4), "major {major}");
+        assert!(middle.abs().abs_diff_eq(Vec3::Y, 1e-4), "middle {middle}");
+        assert!(minor.abs().abs_diff_eq(Vec3::Z, 1e-4), "minor {minor}");
+
+        let (transform, half_extents) = cloud.obb().unwrap();
+        assert!(transform.translation.abs_diff_eq(vec3(1., 2., 3.), 1e-4));
+        assert!(half_extents.abs_diff_eq(vec3(2., 1., 0.5), 1e-4), "half extents {half_extents}");
+    }
+
+    #[test]
+    fn obb_of_a_rotated_box_follows_its_rotation() {
+        let rotation = Quat::from_axis_angle(vec3(1., 2., 3.).normalize(), 0.7);
+        let box_transform = Transform::from_xyz(-4., 0., 2.).with_rotation(rotation);
+        let cloud = box_corners(vec3(2., 1., 0.5), box_transform);
+
+        let (transform, half_extents) = cloud.obb().unwrap();
+        assert!(transform.translation.abs_diff_eq(box_transform.translation, 1e-3));
+        assert!(half_extents.abs_diff_eq(vec3(2., 1., 0.5), 1e-3), "half extents {half_extents}");
+        let (major, _, _) = cloud.principal_axes();
+        assert!(major.dot(rotation * Vec3::X).abs() > 1. - 1e-4, "major {major}");
+    }
+
+    #[test]
+    fn degenerate_clouds_have_no_obb_or_nan_axes() {
+        let empty = PointCloud::default();
+        assert!(empty.obb().is_none());
+        assert_eq!(empty.principal_axes(), (Vec3::X, Vec3::Y, Vec3::Z));
+
+        let single = PointCloud { points: Arc::new(vec![Vec4::new(1., 2., 3., 1.)]) };
+        assert_finite_axes(&single);
+        let (transform, half_extents) = single.obb().unwrap();
+        assert!(transform.translation.abs_diff_eq(vec3(1., 2., 3.), 1e-4));
+        assert!(half_extents.abs_diff_eq(Vec3::ZERO, 1e-4));
+
+        let direction = vec3(1., 1., 0.).normalize();
+        let collinear = PointCloud {
+            points: Arc::new((0..5).map(|step| (direction * step as f32).extend(1.)).collect()),
+        };
+        assert_finite_axes(&collinear);
+        let (major, _, _) = collinear.principal_axes();
+        assert!(major.dot(direction).abs() > 1. - 1e-4, "major {major}");
+        let (_, half_extents) = collinear.obb().unwrap();
+        assert!(half_extents.abs_diff_eq(vec3(2., 0., 0.), 1e-3), "half extents {half_extents}");
+    }
 }
@@ -0,0 +1,380 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::{Extract, Render, RenderApp, RenderSet};
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
+use bevy::render::render_phase::ViewBinnedRenderPhases;
+use bevy::render::render_resource::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer, ImageDataLayout, MapMode, Maintain, PipelineCache, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::renderer::{render_system, RenderDevice, RenderQueue};
+use bevy::render::texture::GpuImage;
+use bevy::render::view::RenderLayers;
+
+use crate::point_cloud::{PointCloud, PointCloudInstances, PointCloudMaterial};
+use crate::transparency::{OrderIndependentTransparencyPipelineId, OrderIndependentTransparent3d};
+
+/// wgpu requires each row of a texture copy to start at a multiple of this many bytes.
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
+/// Bytes per pixel of the `Bgra8UnormSrgb` render target.
+const PIXEL_SIZE: u32 = 4;
+
+/// The entities and target image of a render started by `spawn_point_cloud_render`.
+#[derive(Clone, Debug)]
+pub struct PointCloudRender {
+    /// Filled in by the renderer from the frame after spawning.
+    pub image: Handle<Image>,
+    pub camera: Entity,
+    pub point_cloud: Entity,
+}
+
+impl PointCloudRender {
+    /// Stops the render, if it hasn't been captured yet.
+    pub fn despawn(&self, commands: &mut Commands) {
+        commands.entity(self.camera).despawn_recursive();
+        commands.entity(self.point_cloud).despawn_recursive();
+    }
+}
+
+/// Sent once a render started by `spawn_point_cloud_render` has been read back. The render's
+/// camera and cloud are despawned at the same time.
+#[derive(Clone, Debug, Event)]
+pub struct PointCloudRenderCaptured {
+    /// `PointCloudRender::camera` of the captured render.
+    pub camera: Entity,
+    pub image: Image,
+}
+
+#[derive(Default)]
+enum CaptureState {
+    /// Waiting for a frame that draws the cloud.
+    #[default]
+    Waiting,
+    /// The image is being copied back.
+    Copying,
+    Captured(Image),
+    Finished,
+}
+
+/// Reads back a render's image once its first complete frame has been drawn.
+#[derive(Clone, Component)]
+pub struct PointCloudRenderCapture {
+    point_cloud: Entity,
+    state: Arc<Mutex<CaptureState>>,
+}
+
+#[derive(Component)]
+pub struct ExtractedPointCloudRenderCapture {
+    point_cloud: Entity,
+    image: AssetId<Image>,
+    state: Arc<Mutex<CaptureState>>,
+}
+
+struct CaptureCopy {
+    buffer: Buffer,
+    size: Extent3d,
+    padded_bytes_per_row: u32,
+    mapped: Arc<Mutex<Option<bool>>>,
+    state: Arc<Mutex<CaptureState>>,
+}
+
+/// Copies that have been submitted but not yet mapped.
+#[derive(Default, Resource)]
+pub struct PendingPointCloudRenderCaptures(Vec<CaptureCopy>);
+
+/// Renders a copy of `point_cloud` into a new `size` image, from a camera at `camera_transform`.
+///
+/// The cloud and camera are put on their own render `layer`, so nothing else in the world is
+/// drawn and the cloud doesn't appear in other views. The camera renders until a frame with the
+/// cloud drawn has been copied back, which can take a few frames while pipelines compile. A
+/// `PointCloudRenderCaptured` event then carries the pixels, and the camera and cloud are
+/// despawned. The image asset stays on the GPU, for use as a texture.
+pub fn spawn_point_cloud_render<M: PointCloudMaterial>(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    point_cloud: PointCloud,
+    material: Handle<M>,
+    camera_transform: Transform,
+    size: UVec2,
+    layer: usize,
+) -> PointCloudRender {
+    let size = Extent3d {
+        width: size.x.max(1),
+        height: size.y.max(1),
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("point cloud render"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    let image = images.add(image);
+
+    let render_layers = RenderLayers::layer(layer);
+    let point_cloud = commands
+        .spawn((
+            Name::new("PointCloudRenderCloud"),
+            SpatialBundle::INHERITED_IDENTITY,
+            point_cloud,
+            material,
+            render_layers.clone(),
+        ))
+        .id();
+    let camera = commands
+        .spawn((
+            Name::new("PointCloudRenderCamera"),
+            Camera3dBundle {
+                camera: Camera {
+                    target: RenderTarget::Image(image.clone()),
+                    clear_color: ClearColorConfig::Custom(Color::BLACK),
+                    // Render before the window cameras.
+                    order: -1,
+                    ..default()
+                },
+                transform: camera_transform,
+                ..default()
+            },
+            render_layers,
+            PointCloudRenderCapture {
+                point_cloud,
+                state: default(),
+            },
+        ))
+        .id();
+
+    PointCloudRender {
+        image,
+        camera,
+        point_cloud,
+    }
+}
+
+pub fn extract_point_cloud_render_captures(
+    mut commands: Commands,
+    captures: Extract<Query<(Entity, &Camera, &PointCloudRenderCapture)>>,
+) {
+    for (entity, camera, capture) in &captures {
+        let RenderTarget::Image(image) = &camera.target else {
+            continue;
+        };
+        if !matches!(*capture.state.lock().unwrap(), CaptureState::Waiting) {
+            continue;
+        }
+
+        commands.get_or_spawn(entity).insert(ExtractedPointCloudRenderCapture {
+            point_cloud: capture.point_cloud,
+            image: image.id(),
+            state: capture.state.clone(),
+        });
+    }
+}
+
+/// Copies each waiting render's image to a mappable buffer, once a frame has drawn its cloud.
+/// Runs after the frame is submitted, so the copy sees the finished image.
+pub fn copy_point_cloud_render_captures(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    images: Res<RenderAssets<GpuImage>>,
+    pipeline_cache: Res<PipelineCache>,
+    point_cloud_instances: Res<PointCloudInstances>,
+    transparent_phases: Res<ViewBinnedRenderPhases<OrderIndependentTransparent3d>>,
+    mut pending: ResMut<PendingPointCloudRenderCaptures>,
+    captures: Query<(Entity, &ExtractedPointCloudRenderCapture, Option<&OrderIndependentTransparencyPipelineId>)>,
+) {
+    for (view, capture, oit_pipeline) in &captures {
+        // Pipelines compile in the background, and a frame drawn before they're ready is blank.
+        let composited = oit_pipeline
+            .is_some_and(|pipeline| pipeline_cache.get_render_pipeline(pipeline.0).is_some());
+        let cloud_drawn = match point_cloud_instances.get(&capture.point_cloud) {
+            Some(instance) if instance.num_points == 0 => true,
+            Some(_) => transparent_phases.get(&view).is_some_and(|phase| {
+                phase.batchable_mesh_values.iter().any(|(key, entities)| {
+                    entities.contains(&capture.point_cloud)
+                        && pipeline_cache.get_render_pipeline(key.pipeline).is_some()
+                })
+            }),
+            None => false,
+        };
+        if !composited || !cloud_drawn {
+            continue;
+        }
+        let Some(gpu_image) = images.get(capture.image) else {
+            continue;
+        };
+
+        let size = gpu_image.texture.size();
+        let padded_bytes_per_row = padded_bytes_per_row(size.width);
+        let buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("point cloud render capture buffer"),
+            size: padded_bytes_per_row as BufferAddress * size.height as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("point cloud render capture"),
+        });
+        encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        render_queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(Mutex::new(None));
+        let callback_mapped = mapped.clone();
+        buffer.slice(..).map_async(MapMode::Read, move |result| {
+            *callback_mapped.lock().unwrap() = Some(result.is_ok());
+        });
+
+        *capture.state.lock().unwrap() = CaptureState::Copying;
+        pending.0.push(CaptureCopy {
+            buffer,
+            size,
+            padded_bytes_per_row,
+            mapped,
+            state: capture.state.clone(),
+        });
+    }
+}
+
+/// Turns mapped capture buffers into images for the main world.
+pub fn finish_point_cloud_render_captures(
+    render_device: Res<RenderDevice>,
+    mut pending: ResMut<PendingPointCloudRenderCaptures>,
+) {
+    if pending.0.is_empty() {
+        return;
+    }
+
+    render_device.poll(Maintain::Poll);
+    pending.0.retain(|copy| {
+        let Some(mapped) = *copy.mapped.lock().unwrap() else {
+            return true;
+        };
+
+        let state = if mapped {
+            let data = {
+                let view = copy.buffer.slice(..).get_mapped_range();
+                unpad_rows(&view, copy.size.width * PIXEL_SIZE, copy.padded_bytes_per_row)
+            };
+            copy.buffer.unmap();
+            CaptureState::Captured(Image::new(
+                copy.size,
+                TextureDimension::D2,
+                data,
+                TextureFormat::Bgra8UnormSrgb,
+                RenderAssetUsages::MAIN_WORLD,
+            ))
+        } else {
+            warn!("Failed to read back a point cloud render");
+            // Try again with a later frame.
+            CaptureState::Waiting
+        };
+        *copy.state.lock().unwrap() = state;
+        false
+    });
+}
+
+/// Sends the captured images and despawns their renders.
+pub fn send_point_cloud_render_captures(
+    mut commands: Commands,
+    mut captured: EventWriter<PointCloudRenderCaptured>,
+    captures: Query<(Entity, &PointCloudRenderCapture)>,
+) {
+    for (camera, capture) in &captures {
+        let mut state = capture.state.lock().unwrap();
+        if !matches!(*state, CaptureState::Captured(_)) {
+            continue;
+        }
+        let CaptureState::Captured(image) = std::mem::replace(&mut *state, CaptureState::Finished) else {
+            unreachable!();
+        };
+
+        captured.send(PointCloudRenderCaptured {
+            camera,
+            image,
+        });
+        commands.entity(camera).despawn_recursive();
+        if let Some(point_cloud) = commands.get_entity(capture.point_cloud) {
+            point_cloud.despawn_recursive();
+        }
+    }
+}
+
+/// The copy row size for an image `width` pixels wide.
+fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * PIXEL_SIZE).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// Strips the padding from the end of each copied row.
+fn unpad_rows(data: &[u8], bytes_per_row: u32, padded_bytes_per_row: u32) -> Vec<u8> {
+    data.chunks_exact(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..bytes_per_row as usize])
+        .copied()
+        .collect()
+}
+
+/// Reads back the images of renders started by `spawn_point_cloud_render`.
+pub struct PointCloudRenderPlugin;
+
+impl Plugin for PointCloudRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<PointCloudRenderCaptured>()
+            .add_systems(PreUpdate, send_point_cloud_render_captures);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<PendingPointCloudRenderCaptures>()
+            .add_systems(ExtractSchedule, extract_point_cloud_render_captures)
+            .add_systems(Render, (
+                copy_point_cloud_render_captures
+                    .in_set(RenderSet::Render)
+                    .after(render_system),
+                finish_point_cloud_render_captures.in_set(RenderSet::Cleanup),
+            ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copied_rows_are_unpadded() {
+        let width = 3;
+        let padded = padded_bytes_per_row(width);
+        assert_eq!(padded, 256);
+
+        let mut data = vec![0xff; padded as usize * 2];
+        data[..12].copy_from_slice(&[1; 12]);
+        data[256..268].copy_from_slice(&[2; 12]);
+        let pixels = unpad_rows(&data, width * PIXEL_SIZE, padded);
+        assert_eq!(pixels, [[1; 12], [2; 12]].concat());
+
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+    }
+}
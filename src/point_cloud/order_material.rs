@@ -0,0 +1,56 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{AsBindGroup, AsBindGroupShaderType, ShaderRef, ShaderType};
+use bevy::render::texture::GpuImage;
+
+use crate::point_cloud::PointCloudMaterial;
+
+#[derive(Clone, Default, ShaderType)]
+pub struct PointCloudOrderMaterialUniform {
+    pub hue_first: f32,
+    pub hue_last: f32,
+    pub alpha: f32,
+}
+
+impl AsBindGroupShaderType<PointCloudOrderMaterialUniform> for PointCloudOrderMaterial {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<GpuImage>,
+    ) -> PointCloudOrderMaterialUniform {
+        PointCloudOrderMaterialUniform {
+            hue_first: self.hue_first,
+            hue_last: self.hue_last,
+            alpha: self.alpha.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Colours points by the order they were added to the cloud, from `hue_first` for the oldest
+/// point to `hue_last` for the newest.
+#[derive(Clone, Asset, AsBindGroup, Reflect)]
+#[uniform(0, PointCloudOrderMaterialUniform)]
+pub struct PointCloudOrderMaterial {
+    /// Hue in radians, wrapping every `2 * PI`.
+    pub hue_first: f32,
+    /// Hue in radians, wrapping every `2 * PI`.
+    pub hue_last: f32,
+    pub alpha: f32,
+}
+
+impl Default for PointCloudOrderMaterial {
+    fn default() -> Self {
+        PointCloudOrderMaterial {
+            hue_first: PI * 1.2,
+            hue_last: 0.0,
+            alpha: 0.5,
+        }
+    }
+}
+
+impl PointCloudMaterial for PointCloudOrderMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Path("shaders/point_cloud_order.wgsl".into())
+    }
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use parry3d::na::{Matrix3, Vector3};
+
+use crate::point_cloud::kdtree::KdTree;
+use crate::point_cloud::PointCloud;
+
+pub const DEFAULT_ICP_TOLERANCE: f32 = 1e-6;
+
+/// Point-to-point iterative closest point: finds the rigid transform that moves `source` onto `target`.
+pub fn icp(source: &PointCloud, target: &PointCloud, iterations: usize) -> Transform {
+    icp_with_tolerance(source, target, iterations, DEFAULT_ICP_TOLERANCE)
+}
+
+/// As `icp`, stopping early once the mean squared correspondence distance changes by less than `tolerance`.
+pub fn icp_with_tolerance(
+    source: &PointCloud,
+    target: &PointCloud,
+    iterations: usize,
+    tolerance: f32,
+) -> Transform {
+    let mut rotation = Mat3::IDENTITY;
+    let mut translation = Vec3::ZERO;
+    if source.points.is_empty() || target.points.is_empty() {
+        return Transform::IDENTITY;
+    }
+
+    let target_tree = KdTree::new(target.points.iter().map(|point| point.truncate()));
+    let mut previous_error = f32::INFINITY;
+    for _ in 0..iterations {
+        let pairs = source.points.iter()
+            .map(|point| rotation * point.truncate() + translation)
+            .filter_map(|point| {
+                let (index, distance_squared) = target_tree.nearest(point)?;
+                Some((point, target_tree.point(index), distance_squared))
+            })
+            .collect::<Vec<_>>();
+
+        let count = pairs.len() as f32;
+        let error = pairs.iter().map(|(_, _, distance_squared)| distance_squared).sum::<f32>() / count;
+        if (previous_error - error).abs() < tolerance {
+            break;
+        }
+        previous_error = error;
+
+        let source_centroid = pairs.iter().map(|(s, _, _)| *s).sum::<Vec3>() / count;
+        let target_centroid = pairs.iter().map(|(_, t, _)| *t).sum::<Vec3>() / count;
+        let covariance = pairs.iter()
+            .fold(Matrix3::zeros(), |acc, (s, t, _)| {
+                let s = *s - source_centroid;
+                let t = *t - target_centroid;
+                acc + Vector3::new(s.x, s.y, s.z) * Vector3::new(t.x, t.y, t.z).transpose()
+            });
+
+        let Some(step_rotation) = best_rotation(covariance) else {
+            break;
+        };
+        let step_translation = target_centroid - step_rotation * source_centroid;
+        rotation = step_rotation * rotation;
+        translation = step_rotation * translation + step_translation;
+    }
+
+    Transform {
+        translation,
+        rotation: Quat::from_mat3(&rotation).normalize(),
+        scale: Vec3::ONE,
+    }
+}
+
+fn best_rotation(covariance: Matrix3<f32>) -> Option<Mat3> {
+    let svd = covariance.svd(true, true);
+    let u = svd.u?;
+    let mut v = svd.v_t?.transpose();
+    if (v * u.transpose()).determinant() < 0. {
+        // Flip the least significant axis so we get a rotation rather than a reflection.
+        v.column_mut(2).neg_mut();
+    }
+
+    let rotation = v * u.transpose();
+    Some(Mat3::from_cols_slice(rotation.as_slice()))
+}
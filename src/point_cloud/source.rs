@@ -0,0 +1,52 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+
+use bevy::prelude::*;
+
+use crate::point_cloud::{PointCloud, PointCloudSettings};
+
+/// Feeds points sent from another thread into this entity's `PointCloud`.
+///
+/// Each batch received is appended with `PointCloud::extend`, in local space, once per frame.
+/// The component is removed once every sender has been dropped.
+#[derive(Component)]
+pub struct PointCloudSource {
+    receiver: Mutex<Receiver<Vec<Vec4>>>,
+}
+
+impl PointCloudSource {
+    pub fn new(receiver: Receiver<Vec<Vec4>>) -> PointCloudSource {
+        PointCloudSource {
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// A source and the sender that feeds it.
+    pub fn channel() -> (Sender<Vec<Vec4>>, PointCloudSource) {
+        let (sender, receiver) = channel();
+        (sender, PointCloudSource::new(receiver))
+    }
+}
+
+pub fn receive_point_cloud_sources(
+    mut commands: Commands,
+    settings: Res<PointCloudSettings>,
+    mut sources: Query<(Entity, &PointCloudSource, &mut PointCloud)>,
+) {
+    let point_limit = settings.point_limit();
+    for (entity, source, mut point_cloud) in &mut sources {
+        let receiver = source.receiver.lock().unwrap();
+        loop {
+            match receiver.try_recv() {
+                Ok(points) => {
+                    point_cloud.extend(points, point_limit);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    commands.entity(entity).remove::<PointCloudSource>();
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1093 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use bevy::core_pipeline::bloom::BloomSettings;
+use bevy::core_pipeline::fxaa::Fxaa;
+use bevy::input::InputSystem;
+use bevy::input::mouse::MouseMotion;
+use bevy::math::{vec2, vec3};
+use bevy::prelude::*;
+use bevy::render::render_resource::encase::private::RuntimeSizedArray;
+use bevy::utils::HashMap;
+use bevy::window::{CursorGrabMode, WindowMode};
+
+use crate::annotation::AnnotationPlugin;
+use crate::eraser::EraserPlugin;
+use crate::key_bindings::{Action, KeyBindings, KeyBindingsPlugin, action_just_pressed};
+use crate::physics::{PhysicsPlugin, PhysicsScene, PhysicsWorld, PhysicsWorldBuilt, PhysicsWorldTask, finish_physics_world};
+use crate::point_cloud::{PointCloud, PointCloudAttribute, PointCloudMaterialPlugin, PointCloudPlugin, PointCloudSettings, SharedPointCloudDrawStats};
+use crate::point_cloud::debug::{PointCloudBufferBar, PointCloudDebugPlugin};
+use crate::point_cloud::density_material::{PointCloudDensity, PointCloudDensityMaterial, PointCloudDensityPlugin};
+use crate::point_cloud::distance_material::{DistanceReference, PointCloudDistanceMaterial, distance_range};
+use crate::point_cloud::order_material::PointCloudOrderMaterial;
+use crate::scanner::{LastScanHit, ScanStats, Scanner, ScannerPlugin, TriggerMode};
+#[cfg(feature = "gpu_timing")]
+use crate::transparency::{OIT_COPY_PASS_SPAN, OIT_TRANSPARENT_PASS_SPAN};
+use crate::transparency::OrderIndependentTransparencyPlugin;
+
+/// Settings for the scene assembled by `LidarSandboxPlugin`.
+#[derive(Clone, Debug, Resource)]
+pub struct LidarSandboxConfig {
+    /// Scenes to load into `Scenes`. The first is spawned at startup.
+    pub scenes: Vec<String>,
+    /// The scanner's offset from the camera.
+    pub scanner_transform: Transform,
+    pub scanner_size_setting: f32,
+    pub scanner_trigger_mode: TriggerMode,
+}
+
+impl Default for LidarSandboxConfig {
+    fn default() -> Self {
+        LidarSandboxConfig {
+            scenes: vec!["models/scene.glb#Scene0".into()],
+            scanner_transform: Transform::from_xyz(0.2, -0.1, 0.1),
+            scanner_size_setting: 0.6,
+            scanner_trigger_mode: TriggerMode::Hold,
+        }
+    }
+}
+
+/// Everything needed for a scanning sandbox: the point cloud, scanner and physics plugins, a
+/// free camera carrying a scanner, a light, the configured scenes and the debug overlays.
+///
+/// Add it after `DefaultPlugins`.
+#[derive(Default)]
+pub struct LidarSandboxPlugin {
+    pub config: LidarSandboxConfig,
+}
+
+impl Plugin for LidarSandboxPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins((
+                OrderIndependentTransparencyPlugin::default(),
+                PointCloudPlugin,
+                PointCloudMaterialPlugin::<PointCloudDistanceMaterial>::default(),
+                PointCloudMaterialPlugin::<PointCloudOrderMaterial>::default(),
+                PointCloudDensityPlugin,
+                PointCloudDebugPlugin,
+                PhysicsPlugin,
+                ScannerPlugin,
+                EraserPlugin,
+                AnnotationPlugin,
+                KeyBindingsPlugin,
+            ))
+            .insert_resource(self.config.clone())
+            .add_systems(Startup, startup)
+            .add_systems(PostStartup, restore_free_cam_pose)
+            .add_systems(PreUpdate, grab_cursor_on_click.after(InputSystem))
+            .add_systems(Last, save_free_cam_pose.run_if(on_event::<AppExit>()))
+            .add_systems(Update, (
+                move_free_cam,
+                zoom_free_cam,
+                (
+                    reset_free_cam_zoom.run_if(action_just_pressed(Action::ResetZoom)),
+                    toggle_cursor_grab.run_if(action_just_pressed(Action::ToggleCursorGrab)),
+                    toggle_lights.run_if(action_just_pressed(Action::ToggleLights)),
+                    clear_scan.run_if(action_just_pressed(Action::ClearScan)),
+                    toggle_boost.run_if(action_just_pressed(Action::ToggleBoost)),
+                    toggle_fullscreen.run_if(action_just_pressed(Action::ToggleFullscreen)),
+                    cycle_anti_aliasing.run_if(action_just_pressed(Action::CycleAntiAliasing)),
+                    toggle_point_cloud_fade.run_if(action_just_pressed(Action::TogglePointCloudFade)),
+                    toggle_density_heatmap.run_if(action_just_pressed(Action::ToggleDensityHeatmap)),
+                    toggle_scanner_freeze.run_if(action_just_pressed(Action::ToggleScannerFreeze)),
+                    toggle_buffer_bar.run_if(action_just_pressed(Action::ToggleBufferBar)),
+                    toggle_range_rings.run_if(action_just_pressed(Action::ToggleRangeRings)),
+                    snapshot_scan.run_if(action_just_pressed(Action::SnapshotScan)),
+                    toggle_bloom.run_if(action_just_pressed(Action::ToggleBloom)),
+                    toggle_axes.run_if(action_just_pressed(Action::ToggleAxes)),
+                    fit_distance_range.run_if(action_just_pressed(Action::FitDistanceRange)),
+                    record_camera_keyframe.run_if(action_just_pressed(Action::RecordCameraKeyframe)),
+                    toggle_camera_path.run_if(action_just_pressed(Action::PlayCameraPath)),
+                ),
+                // Scans of the previous scene shouldn't linger in a newly built one.
+                clear_scan.run_if(on_event::<PhysicsWorldBuilt>()).after(finish_physics_world),
+                play_camera_path.after(move_free_cam),
+                draw_axes,
+                apply_anti_alias,
+                switch_scene,
+                update_debug_text,
+                remove_emissive,
+            ))
+            .init_resource::<FreeCamPersistence>()
+            .init_resource::<CursorSettings>()
+            .init_resource::<CameraPath>()
+            .register_type::<AntiAlias>()
+            .init_resource::<AntiAlias>()
+            .register_type::<AxesGizmo>()
+            .init_resource::<AxesGizmo>()
+            .register_type::<EmissivePolicy>()
+            .init_resource::<EmissivePolicy>()
+            .insert_resource(ClearColor(Color::BLACK))
+            .insert_resource(AmbientLight::NONE);
+
+        #[cfg(feature = "gpu_timing")]
+        app.add_plugins(bevy::render::diagnostic::RenderDiagnosticsPlugin);
+    }
+}
+
+fn startup(
+    asset_server: Res<AssetServer>,
+    config: Res<LidarSandboxConfig>,
+    cursor_settings: Res<CursorSettings>,
+    mut commands: Commands,
+    mut distance_materials: ResMut<Assets<PointCloudDistanceMaterial>>,
+    mut windows: Query<&mut Window>,
+) {
+    if cursor_settings.grab_cursor_on_start {
+        for mut window in &mut windows {
+            window.cursor.grab_mode = CursorGrabMode::Locked;
+            window.cursor.visible = false;
+        }
+    }
+
+    let distance_material = distance_materials.add(PointCloudDistanceMaterial::default());
+    let point_cloud = commands
+        .spawn((
+            Name::new("PointCloud"),
+            SpatialBundle::INHERITED_IDENTITY,
+            PointCloud::default(),
+            distance_material,
+            ClearPointCloud,
+        ))
+        .id();
+
+    commands
+        .spawn((
+            Name::new("Camera"),
+            Camera3dBundle {
+                transform: Transform::from_xyz(2.0, 2.0, 2.0)
+                    .looking_at(vec3(0.0, 1.5, 0.0), Vec3::Y),
+                ..default()
+            },
+            VisibilityBundle::default(),
+            FreeCam::default(),
+        ))
+        .with_children(|children| {
+            children
+                .spawn((
+                    Name::new("Scanner"),
+                    SpatialBundle {
+                        transform: config.scanner_transform,
+                        ..default()
+                    },
+                    Scanner {
+                        size_setting: config.scanner_size_setting,
+                        trigger_mode: config.scanner_trigger_mode,
+                        point_cloud,
+                        ..default()
+                    },
+                ));
+        });
+
+    commands.spawn((
+        Name::new("Light"),
+        PointLightBundle {
+            transform: Transform::from_translation(Vec3::ONE),
+            ..default()
+        },
+    ));
+
+    let scenes = Scenes {
+        scenes: config.scenes.iter()
+            .map(|path| asset_server.load(path.clone()))
+            .collect(),
+        active: 0,
+    };
+    if let Some(scene) = scenes.scenes.first() {
+        spawn_scene(&mut commands, scene.clone());
+    }
+    commands.insert_resource(scenes);
+
+    commands.spawn((
+        Name::new("DebugText"),
+        TextBundle {
+            text: Text {
+                sections: vec![TextSection::new("", TextStyle::default())],
+                ..default()
+            },
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::ZERO,
+                left: Val::ZERO,
+                ..default()
+            },
+            ..default()
+        },
+        DebugText,
+    ));
+}
+
+#[derive(Clone, Debug, Resource)]
+pub struct CursorSettings {
+    /// Lock and hide the cursor at startup. When false, it stays free until grabbed with `Action::ToggleCursorGrab` or a left click.
+    pub grab_cursor_on_start: bool,
+}
+
+impl Default for CursorSettings {
+    fn default() -> Self {
+        CursorSettings {
+            grab_cursor_on_start: true,
+        }
+    }
+}
+
+/// The scenes that can be switched between with `Action::SelectScene`.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct Scenes {
+    pub scenes: Vec<Handle<Scene>>,
+    pub active: usize,
+}
+
+fn spawn_scene(commands: &mut Commands, scene: Handle<Scene>) {
+    commands.spawn((
+        Name::new("Scene"),
+        SceneBundle {
+            scene,
+            ..default()
+        },
+        PhysicsScene,
+    ));
+}
+
+fn switch_scene(
+    mut commands: Commands,
+    key_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    scenes: Option<ResMut<Scenes>>,
+    mut physics_world: ResMut<PhysicsWorld>,
+    current_scenes: Query<Entity, With<PhysicsScene>>,
+    mut point_clouds: Query<&mut PointCloud, With<ClearPointCloud>>,
+) {
+    let Some(mut scenes) = scenes else {
+        return;
+    };
+    let Some(index) = key_bindings.bindings.iter()
+        .find_map(|(action, key_code)| match action {
+            Action::SelectScene(index) if key_input.just_pressed(*key_code) => Some(*index as usize),
+            _ => None,
+        }) else {
+        return;
+    };
+    let Some(scene) = scenes.scenes.get(index).cloned() else {
+        return;
+    };
+    if index == scenes.active {
+        return;
+    }
+
+    for entity in &current_scenes {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_scene(&mut commands, scene);
+    scenes.active = index;
+
+    // Drop the old collider so nothing is scanned until the new scene has been baked.
+    physics_world.replace_static(PhysicsWorld::default());
+    for mut point_cloud in &mut point_clouds {
+        point_cloud.clear();
+    }
+}
+
+pub enum FreeCamBinding {
+    Move(Vec3),
+    MoveModify(f32),
+    Zoom(f32),
+}
+
+#[derive(Component)]
+pub struct FreeCam {
+    pub look: Vec2,
+    pub max_look: f32,
+    pub move_speed: f32,
+    pub look_speed: f32,
+    /// Vertical field of view restored by the zoom reset, in radians.
+    pub default_fov: f32,
+    pub fov_range: Vec2,
+    /// Fraction of the current field of view to zoom by per second.
+    pub zoom_speed: f32,
+    pub key_bindings: Vec<(KeyCode, FreeCamBinding)>,
+}
+
+impl Default for FreeCam {
+    fn default() -> Self {
+        FreeCam {
+            look: Vec2::ZERO,
+            max_look: std::f32::consts::PI * 0.4,
+            move_speed: 2.0,
+            look_speed: 0.1,
+            default_fov: std::f32::consts::FRAC_PI_4,
+            fov_range: vec2(0.02, 2.0),
+            zoom_speed: 1.5,
+            key_bindings: vec![
+                (KeyCode::KeyW, FreeCamBinding::Move(Vec3::NEG_Z)),
+                (KeyCode::KeyS, FreeCamBinding::Move(Vec3::Z)),
+                (KeyCode::KeyQ, FreeCamBinding::Move(Vec3::NEG_Y)),
+                (KeyCode::KeyE, FreeCamBinding::Move(Vec3::Y)),
+                (KeyCode::KeyA, FreeCamBinding::Move(Vec3::NEG_X)),
+                (KeyCode::KeyD, FreeCamBinding::Move(Vec3::X)),
+                (KeyCode::ShiftLeft, FreeCamBinding::MoveModify(5.)),
+                (KeyCode::KeyZ, FreeCamBinding::Zoom(1.)),
+                (KeyCode::KeyX, FreeCamBinding::Zoom(-1.)),
+            ],
+        }
+    }
+}
+
+/// Saves the free camera's pose on exit and restores it on startup.
+#[derive(Clone, Debug, Resource)]
+pub struct FreeCamPersistence {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for FreeCamPersistence {
+    fn default() -> Self {
+        FreeCamPersistence {
+            enabled: false,
+            path: PathBuf::from("free_cam.txt"),
+        }
+    }
+}
+
+fn format_free_cam_pose(transform: &Transform, free_cam: &FreeCam) -> String {
+    let t = transform.translation;
+    format!("{} {} {} {} {}\n", t.x, t.y, t.z, free_cam.look.x, free_cam.look.y)
+}
+
+fn parse_free_cam_pose(contents: &str) -> Option<(Vec3, Vec2)> {
+    let values = contents.split_whitespace()
+        .map(|value| value.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match values[..] {
+        [x, y, z, look_x, look_y] => Some((vec3(x, y, z), vec2(look_x, look_y))),
+        _ => None,
+    }
+}
+
+fn restore_free_cam_pose(
+    persistence: Res<FreeCamPersistence>,
+    mut cameras: Query<(&mut FreeCam, &mut Transform)>,
+) {
+    if !persistence.enabled {
+        return;
+    }
+
+    let contents = match fs::read_to_string(&persistence.path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => {
+            warn!("Failed to read camera pose from {:?}: {}", persistence.path, err);
+            return;
+        }
+    };
+    let Some((translation, look)) = parse_free_cam_pose(&contents) else {
+        warn!("Ignoring malformed camera pose in {:?}", persistence.path);
+        return;
+    };
+
+    for (mut free_cam, mut transform) in &mut cameras {
+        let look = vec2(look.x, look.y.clamp(-free_cam.max_look, free_cam.max_look));
+        free_cam.look = look;
+        transform.translation = translation;
+        transform.rotation = Quat::from_rotation_y(look.x)
+            * Quat::from_rotation_x(look.y);
+    }
+}
+
+fn save_free_cam_pose(
+    persistence: Res<FreeCamPersistence>,
+    cameras: Query<(&FreeCam, &Transform)>,
+) {
+    if !persistence.enabled {
+        return;
+    }
+
+    let Some((free_cam, transform)) = cameras.iter().next() else {
+        return;
+    };
+    if let Err(err) = fs::write(&persistence.path, format_free_cam_pose(transform, free_cam)) {
+        warn!("Failed to save camera pose to {:?}: {}", persistence.path, err);
+    }
+}
+
+pub fn toggle_cursor_grab(
+    mut windows: Query<&mut Window>,
+) {
+    for mut window in &mut windows {
+        let (grab_mode, visible) = if window.cursor.visible {
+            (CursorGrabMode::Locked, false)
+        } else {
+            (CursorGrabMode::None, true)
+        };
+
+        window.cursor.grab_mode = grab_mode;
+        window.cursor.visible = visible;
+    }
+}
+
+/// Grabs a free cursor when the window is left-clicked. The click is consumed so that it doesn't
+/// also start a scan.
+pub fn grab_cursor_on_click(
+    mut mouse_input: ResMut<ButtonInput<MouseButton>>,
+    mut windows: Query<&mut Window>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    for mut window in &mut windows {
+        if !window.focused || !window.cursor.visible || window.cursor_position().is_none() {
+            continue;
+        }
+
+        window.cursor.grab_mode = CursorGrabMode::Locked;
+        window.cursor.visible = false;
+        mouse_input.reset(MouseButton::Left);
+    }
+}
+
+pub fn move_free_cam(
+    time: Res<Time>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut cameras: Query<(&mut FreeCam, &mut Transform)>,
+) {
+    let look_input = mouse_motion.read()
+        .fold(Vec2::ZERO, |acc, input| acc + input.delta)
+        * time.delta_seconds() * -1.0;
+
+    for (mut free_cam, mut transform) in &mut cameras {
+        let (move_input, move_modifier) = free_cam.key_bindings.iter()
+            .fold((Vec3::ZERO, 1.), |(input, modifier), (key_code, binding)| {
+                if key_input.pressed(*key_code) {
+                    match binding {
+                        FreeCamBinding::Move(x) => (input + *x, modifier),
+                        FreeCamBinding::MoveModify(x) => (input, modifier * *x),
+                        FreeCamBinding::Zoom(_) => (input, modifier),
+                    }
+                } else {
+                    (input, modifier)
+                }
+            });
+        let mut look = free_cam.look + look_input * free_cam.look_speed;
+        look.y = look.y.clamp(-free_cam.max_look, free_cam.max_look);
+        free_cam.look = look;
+        transform.rotation = Quat::from_rotation_y(look.x)
+            * Quat::from_rotation_x(look.y);
+
+        let move_delta = transform.rotation * move_input * move_modifier * free_cam.move_speed * time.delta_seconds();
+        transform.translation += move_delta;
+    }
+}
+
+pub fn zoom_free_cam(
+    time: Res<Time>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut cameras: Query<(&FreeCam, &mut Projection)>,
+) {
+    for (free_cam, mut projection) in &mut cameras {
+        let Projection::Perspective(perspective) = projection.as_ref() else {
+            continue;
+        };
+
+        let zoom_input = free_cam.key_bindings.iter()
+            .filter(|(key_code, _)| key_input.pressed(*key_code))
+            .fold(0., |acc, (_, binding)| match binding {
+                FreeCamBinding::Zoom(x) => acc + *x,
+                _ => acc,
+            });
+        if zoom_input == 0. {
+            continue;
+        }
+
+        let fov = perspective.fov * (-zoom_input * free_cam.zoom_speed * time.delta_seconds()).exp();
+        let fov = fov.clamp(free_cam.fov_range.x, free_cam.fov_range.y);
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = fov;
+        }
+    }
+}
+
+pub fn reset_free_cam_zoom(
+    mut cameras: Query<(&FreeCam, &mut Projection)>,
+) {
+    for (free_cam, mut projection) in &mut cameras {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = free_cam.default_fov;
+        }
+    }
+}
+
+/// Keyframed free camera poses, replayed along a Catmull-Rom spline for flythroughs.
+#[derive(Clone, Debug, Resource)]
+pub struct CameraPath {
+    pub keyframes: Vec<Transform>,
+    /// Playback speed, in keyframes per second.
+    pub speed: f32,
+    /// How far along the path playback is, in keyframes, or `None` when stopped.
+    pub playback: Option<f32>,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        CameraPath {
+            keyframes: Vec::new(),
+            speed: 0.5,
+            playback: None,
+        }
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+impl CameraPath {
+    /// The pose `position` keyframes along the path. The spline passes through every keyframe,
+    /// and rotations are slerped between neighbouring keyframes.
+    pub fn sample(&self, position: f32) -> Option<Transform> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let position = position.clamp(0.0, last as f32);
+        let index = (position.floor() as usize).min(last.saturating_sub(1));
+        let t = position - index as f32;
+        let keyframe = |i: isize| self.keyframes[i.clamp(0, last as isize) as usize];
+        let i = index as isize;
+        let (k0, k1, k2, k3) = (keyframe(i - 1), keyframe(i), keyframe(i + 1), keyframe(i + 2));
+        Some(Transform {
+            translation: catmull_rom(k0.translation, k1.translation, k2.translation, k3.translation, t),
+            rotation: k1.rotation.slerp(k2.rotation, t),
+            scale: k1.scale.lerp(k2.scale, t),
+        })
+    }
+}
+
+fn record_camera_keyframe(
+    mut camera_path: ResMut<CameraPath>,
+    cameras: Query<&Transform, With<FreeCam>>,
+) {
+    let Some(transform) = cameras.iter().next() else {
+        return;
+    };
+    camera_path.keyframes.push(*transform);
+    info!("Recorded camera keyframe {}", camera_path.keyframes.len());
+}
+
+fn toggle_camera_path(
+    mut camera_path: ResMut<CameraPath>,
+) {
+    camera_path.playback = match camera_path.playback {
+        None if camera_path.keyframes.len() > 1 => Some(0.0),
+        _ => None,
+    };
+}
+
+/// Moves the free camera along the path while it plays. When playback ends, the camera's look
+/// angles are taken from the final pose so manual control carries on from there.
+pub fn play_camera_path(
+    time: Res<Time>,
+    mut camera_path: ResMut<CameraPath>,
+    mut cameras: Query<(&mut FreeCam, &mut Transform)>,
+) {
+    let Some(position) = camera_path.playback else {
+        return;
+    };
+    let end = camera_path.keyframes.len().saturating_sub(1) as f32;
+    let position = (position + camera_path.speed * time.delta_seconds()).min(end);
+    let Some(pose) = camera_path.sample(position) else {
+        camera_path.playback = None;
+        return;
+    };
+
+    for (mut free_cam, mut transform) in &mut cameras {
+        *transform = pose;
+        let (yaw, pitch, _) = pose.rotation.to_euler(EulerRot::YXZ);
+        free_cam.look = vec2(yaw, pitch);
+    }
+    camera_path.playback = (position < end).then_some(position);
+}
+
+fn toggle_lights(
+    mut lights: Query<
+        &mut Visibility,
+        Or<(With<PointLight>, With<SpotLight>, With<DirectionalLight>)>,
+    >,
+) {
+    for mut visibility in &mut lights {
+        let new_visibility = match *visibility {
+            Visibility::Inherited | Visibility::Visible => Visibility::Hidden,
+            Visibility::Hidden => Visibility::Inherited,
+        };
+        *visibility = new_visibility;
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct ClearPointCloud;
+
+fn clear_scan(
+    mut point_clouds: Query<&mut PointCloud, With<ClearPointCloud>>,
+) {
+    for mut point_cloud in &mut point_clouds {
+        point_cloud.clear();
+    }
+}
+
+/// A frozen copy of a scanned cloud, taken with `snapshot_scan`.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct PointCloudSnapshot {
+    pub source: Entity,
+}
+
+fn snapshot_scan(
+    mut commands: Commands,
+    point_clouds: Query<(Entity, &PointCloud, &Transform, Option<&Handle<PointCloudDistanceMaterial>>), With<ClearPointCloud>>,
+    snapshots: Query<(), With<PointCloudSnapshot>>,
+) {
+    let mut index = snapshots.iter().count();
+    for (entity, point_cloud, transform, material) in &point_clouds {
+        index += 1;
+        // Sharing the Arc makes this cheap; the live cloud copies its points on its next change.
+        let mut snapshot = commands.spawn((
+            Name::new(format!("PointCloudSnapshot{}", index)),
+            SpatialBundle::from_transform(*transform),
+            point_cloud.clone(),
+            PointCloudSnapshot {
+                source: entity,
+            },
+        ));
+        if let Some(material) = material {
+            snapshot.insert(material.clone());
+        }
+    }
+}
+
+fn toggle_boost(
+    mut scanners: Query<&mut Scanner>,
+) {
+    let default = Scanner::default();
+    for mut scanner in &mut scanners {
+        if scanner.interval_range[0] == default.interval_range[0] {
+            scanner.interval_range = vec2(0.00001, 0.00001);
+        } else {
+            scanner.interval_range = default.interval_range;
+        }
+    }
+}
+
+fn toggle_scanner_freeze(
+    mut scanners: Query<&mut Scanner>,
+) {
+    for mut scanner in &mut scanners {
+        scanner.frozen = !scanner.frozen;
+    }
+}
+
+fn toggle_buffer_bar(
+    mut bars: Query<&mut Visibility, With<PointCloudBufferBar>>,
+) {
+    for mut visibility in &mut bars {
+        let new_visibility = match *visibility {
+            Visibility::Inherited | Visibility::Visible => Visibility::Hidden,
+            Visibility::Hidden => Visibility::Inherited,
+        };
+        *visibility = new_visibility;
+    }
+}
+
+fn toggle_range_rings(
+    mut materials: ResMut<Assets<PointCloudDistanceMaterial>>,
+) {
+    for (_, material) in materials.iter_mut() {
+        material.ring_spacing = if material.ring_spacing > 0.0 {
+            0.0
+        } else {
+            1.0
+        };
+    }
+}
+
+/// Fraction of the fitted distance range added to each end by `fit_distance_range`.
+const FIT_DISTANCE_MARGIN: f32 = 0.05;
+
+fn fit_distance_range(
+    mut materials: ResMut<Assets<PointCloudDistanceMaterial>>,
+    cameras: Query<&GlobalTransform, With<FreeCam>>,
+    point_clouds: Query<(&PointCloud, &GlobalTransform, &Handle<PointCloudDistanceMaterial>)>,
+) {
+    let camera_position = cameras.iter().next()
+        .map_or(Vec3::ZERO, |transform| transform.translation());
+
+    // Clouds can share a material, so fit it to all of them.
+    let mut ranges: HashMap<AssetId<PointCloudDistanceMaterial>, (f32, f32)> = HashMap::default();
+    for (point_cloud, transform, handle) in &point_clouds {
+        let Some(material) = materials.get(handle) else {
+            continue;
+        };
+        let reference = match material.reference {
+            DistanceReference::Camera => camera_position,
+            DistanceReference::WorldOrigin => Vec3::ZERO,
+            DistanceReference::Fixed(point) => point,
+        };
+
+        let world_from_local = transform.affine();
+        let points = point_cloud.points.iter()
+            .map(|point| world_from_local.transform_point3(point.truncate()));
+        let Some((min, max)) = distance_range(reference, points) else {
+            continue;
+        };
+        ranges.entry(handle.id())
+            .and_modify(|range| *range = (range.0.min(min), range.1.max(max)))
+            .or_insert((min, max));
+    }
+
+    for (id, range) in ranges {
+        if let Some(material) = materials.get_mut(id) {
+            material.fit_range(range, FIT_DISTANCE_MARGIN);
+        }
+    }
+}
+
+/// Toggles Bevy's bloom on the free cam, which stands in for a dedicated glow pass over the point
+/// accumulation. Bloom only has bright colours to work with on an HDR target, so HDR is switched
+/// on with it.
+fn toggle_bloom(
+    mut commands: Commands,
+    mut cameras: Query<(Entity, &mut Camera, Has<BloomSettings>), With<FreeCam>>,
+) {
+    for (entity, mut camera, has_bloom) in &mut cameras {
+        camera.hdr = !has_bloom;
+        if has_bloom {
+            commands.entity(entity).remove::<BloomSettings>();
+        } else {
+            commands.entity(entity).insert(BloomSettings::NATURAL);
+        }
+    }
+}
+
+/// Red, green and blue lines along X, Y and Z at the world origin, and optionally at each scanner.
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct AxesGizmo {
+    pub enabled: bool,
+    pub length: f32,
+    pub show_at_scanners: bool,
+}
+
+impl Default for AxesGizmo {
+    fn default() -> Self {
+        AxesGizmo {
+            enabled: false,
+            length: 1.0,
+            show_at_scanners: false,
+        }
+    }
+}
+
+fn toggle_axes(
+    mut axes: ResMut<AxesGizmo>,
+) {
+    axes.enabled = !axes.enabled;
+}
+
+fn draw_axes(
+    axes: Res<AxesGizmo>,
+    mut gizmos: Gizmos,
+    scanners: Query<&GlobalTransform, With<Scanner>>,
+) {
+    if !axes.enabled {
+        return;
+    }
+
+    gizmos.axes(Transform::IDENTITY, axes.length);
+    if axes.show_at_scanners {
+        for transform in &scanners {
+            gizmos.axes(*transform, axes.length);
+        }
+    }
+}
+
+fn toggle_fullscreen(
+    mut windows: Query<&mut Window>,
+) {
+    for mut window in &mut windows {
+        let new_mode = if window.mode == WindowMode::Windowed {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        };
+        window.mode = new_mode;
+    }
+}
+
+/// How the sandbox cameras are anti-aliased.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource, Reflect)]
+#[reflect(Resource)]
+pub enum AntiAlias {
+    None,
+    /// Multisampling with this many samples: 2, 4 or 8. The transparency accumulation targets
+    /// are multisampled too, which is expensive for float formats.
+    Msaa(u32),
+    /// Draws everything at one sample and runs FXAA over the final image, transparency included.
+    Fxaa,
+}
+
+impl Default for AntiAlias {
+    fn default() -> Self {
+        AntiAlias::Msaa(4)
+    }
+}
+
+impl AntiAlias {
+    pub fn msaa(self) -> Msaa {
+        match self {
+            AntiAlias::Msaa(2) => Msaa::Sample2,
+            AntiAlias::Msaa(8) => Msaa::Sample8,
+            AntiAlias::Msaa(samples) if samples > 1 => Msaa::Sample4,
+            _ => Msaa::Off,
+        }
+    }
+}
+
+fn apply_anti_alias(
+    mut commands: Commands,
+    anti_alias: Res<AntiAlias>,
+    mut msaa: ResMut<Msaa>,
+    cameras: Query<Entity, With<FreeCam>>,
+    added_cameras: Query<(), Added<FreeCam>>,
+) {
+    if !anti_alias.is_changed() && added_cameras.is_empty() {
+        return;
+    }
+
+    let new_msaa = anti_alias.msaa();
+    if *msaa != new_msaa {
+        *msaa = new_msaa;
+    }
+    for entity in &cameras {
+        if *anti_alias == AntiAlias::Fxaa {
+            commands.entity(entity).insert(Fxaa::default());
+        } else {
+            commands.entity(entity).remove::<Fxaa>();
+        }
+    }
+}
+
+fn cycle_anti_aliasing(
+    mut anti_alias: ResMut<AntiAlias>,
+) {
+    *anti_alias = match *anti_alias {
+        AntiAlias::Msaa(_) => AntiAlias::Fxaa,
+        AntiAlias::Fxaa => AntiAlias::None,
+        AntiAlias::None => AntiAlias::Msaa(4),
+    };
+}
+
+fn toggle_point_cloud_fade(
+    mut settings: ResMut<PointCloudSettings>,
+) {
+    settings.global_alpha = if settings.global_alpha < 1.0 {
+        1.0
+    } else {
+        0.2
+    };
+}
+
+/// Swaps the scan between distance colouring and a density heatmap.
+fn toggle_density_heatmap(
+    mut commands: Commands,
+    mut distance_materials: ResMut<Assets<PointCloudDistanceMaterial>>,
+    mut density_materials: ResMut<Assets<PointCloudDensityMaterial>>,
+    point_clouds: Query<(Entity, Has<PointCloudDensity>), With<ClearPointCloud>>,
+) {
+    for (entity, has_density) in &point_clouds {
+        // Only one material at a time, so the cloud isn't drawn twice.
+        if has_density {
+            commands.entity(entity)
+                .remove::<(PointCloudDensity, PointCloudAttribute, Handle<PointCloudDensityMaterial>)>()
+                .insert(distance_materials.add(PointCloudDistanceMaterial::default()));
+        } else {
+            commands.entity(entity)
+                .remove::<Handle<PointCloudDistanceMaterial>>()
+                .insert((PointCloudDensity::default(), density_materials.add(PointCloudDensityMaterial::default())));
+        }
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct DebugText;
+
+fn update_debug_text(
+    mut text_query: Query<&mut Text, With<DebugText>>,
+    point_cloud_query: Query<&PointCloud, With<ClearPointCloud>>,
+    scan_stats: Res<ScanStats>,
+    draw_stats: Res<SharedPointCloudDrawStats>,
+    physics_tasks: Query<(), With<PhysicsWorldTask>>,
+    scanners: Query<(&GlobalTransform, &LastScanHit), With<Scanner>>,
+    #[cfg(feature = "gpu_timing")]
+    diagnostics: Res<bevy::diagnostic::DiagnosticsStore>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+
+    let section = &mut text.sections[0];
+    section.value.clear();
+
+    if let Ok(point_cloud) = point_cloud_query.get_single() {
+        write!(&mut section.value, "Points: {}", point_cloud.points.len()).unwrap();
+    }
+
+    write!(
+        &mut section.value,
+        "\nHits: {}/{} (total {}/{})",
+        scan_stats.frame.hits,
+        scan_stats.frame.total(),
+        scan_stats.total.hits,
+        scan_stats.total.total(),
+    ).unwrap();
+
+    let draw_stats = draw_stats.get();
+    write!(
+        &mut section.value,
+        "\nDraws: {} ({} instances)",
+        draw_stats.draw_calls,
+        draw_stats.instances,
+    ).unwrap();
+
+    for (transform, last_hit) in &scanners {
+        let Some(hit) = last_hit.0 else {
+            continue;
+        };
+
+        // Scanners look down -Z, so measure azimuth from -Z towards +X and elevation towards +Y.
+        let local = transform.affine().inverse().transform_point3(hit);
+        let azimuth = local.x.atan2(-local.z);
+        let elevation = local.y.atan2(local.xz().length());
+        write!(
+            &mut section.value,
+            "\nAim: az {:.2}\u{b0} el {:.2}\u{b0} range {:.3}m",
+            azimuth.to_degrees(),
+            elevation.to_degrees(),
+            local.length(),
+        ).unwrap();
+    }
+
+    #[cfg(feature = "gpu_timing")]
+    for (label, span) in [("Points", OIT_TRANSPARENT_PASS_SPAN), ("OIT copy", OIT_COPY_PASS_SPAN)] {
+        let path = bevy::diagnostic::DiagnosticPath::new(format!("render/{}/elapsed_gpu", span));
+        if let Some(elapsed) = diagnostics.get(&path).and_then(|diagnostic| diagnostic.smoothed()) {
+            write!(&mut section.value, "\n{} GPU: {:.3}ms", label, elapsed).unwrap();
+        }
+    }
+
+    if !physics_tasks.is_empty() {
+        write!(&mut section.value, "\nLoading collider...").unwrap();
+    }
+}
+
+/// What `remove_emissive` does to scene materials that glow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource, Reflect)]
+#[reflect(Resource)]
+pub enum EmissivePolicy {
+    /// Leave emissive materials alone.
+    Keep,
+    /// Swap in a copy of the material without emission.
+    #[default]
+    Zero,
+    /// Remove the material from the entity.
+    Remove,
+}
+
+fn remove_emissive(
+    mut commands: Commands,
+    policy: Res<EmissivePolicy>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut query: Query<(Entity, &mut Handle<StandardMaterial>), Changed<Handle<StandardMaterial>>>,
+    mut replacements: Local<HashMap<AssetId<StandardMaterial>, Handle<StandardMaterial>>>,
+) {
+    if *policy == EmissivePolicy::Keep {
+        return;
+    }
+
+    for (entity, mut handle) in &mut query {
+        if let Some(replacement) = replacements.get(&handle.id()) {
+            *handle = replacement.clone();
+            continue;
+        }
+
+        let Some(material) = materials.get(&*handle) else {
+            continue;
+        };
+
+        let max_c = material.emissive.red
+            .max(material.emissive.green)
+            .max(material.emissive.blue);
+        if material.emissive_texture.is_none() && max_c * material.emissive.alpha <= 0.0 {
+            continue;
+        }
+
+        if *policy == EmissivePolicy::Remove {
+            commands.entity(entity).remove::<Handle<StandardMaterial>>();
+            continue;
+        }
+
+        // Swap in a copy without emission so the geometry still draws, just without glowing.
+        let mut replacement = material.clone();
+        replacement.emissive = LinearRgba::BLACK;
+        replacement.emissive_texture = None;
+        let replacement = materials.add(replacement);
+        replacements.insert(handle.id(), replacement.clone());
+        *handle = replacement;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(window: Window) -> (Window, bool) {
+        let mut app = App::new();
+        app
+            .init_resource::<ButtonInput<MouseButton>>()
+            .add_systems(Update, grab_cursor_on_click);
+        let window = app.world_mut().spawn(window).id();
+        app.world_mut().resource_mut::<ButtonInput<MouseButton>>().press(MouseButton::Left);
+        app.update();
+
+        let pressed = app.world().resource::<ButtonInput<MouseButton>>().pressed(MouseButton::Left);
+        (app.world_mut().entity_mut(window).take::<Window>().unwrap(), pressed)
+    }
+
+    #[test]
+    fn clicking_a_free_cursor_grabs_it_and_consumes_the_click() {
+        let mut window = Window::default();
+        window.set_cursor_position(Some(vec2(10., 10.)));
+
+        let (window, pressed) = click(window);
+        assert_eq!(window.cursor.grab_mode, CursorGrabMode::Locked);
+        assert!(!window.cursor.visible);
+        assert!(!pressed);
+    }
+
+    #[test]
+    fn clicking_a_grabbed_cursor_scans() {
+        let mut window = Window::default();
+        window.cursor.grab_mode = CursorGrabMode::Locked;
+        window.cursor.visible = false;
+
+        let (window, pressed) = click(window);
+        assert_eq!(window.cursor.grab_mode, CursorGrabMode::Locked);
+        assert!(pressed);
+    }
+}
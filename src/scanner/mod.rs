@@ -0,0 +1,591 @@
+use std::f32::consts::PI;
+
+use bevy::color::palettes::css::{ORANGE, SKY_BLUE};
+use bevy::ecs::entity::EntityHashMap;
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::math::vec2;
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::physics::PhysicsWorld;
+
+use crate::point_cloud::{DEFAULT_POINT_SIZE, PointCloud, PointCloudSettings};
+
+pub use patterns::{ConePattern, CrossBurstPattern, LineFanPattern, ScanContext, ScanPattern, cone_sample_direction, cross_burst_offset};
+
+pub mod patterns;
+
+/// How the scan button drives `Scanner::active`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum TriggerMode {
+    /// Scan while the button is held.
+    #[default]
+    Hold,
+    /// Each press starts or stops scanning.
+    Toggle,
+}
+
+/// Where a scanner's rays start from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum OriginMode {
+    /// The scanner's own position, plus the aperture jitter.
+    #[default]
+    SelfTransform,
+    /// The position of the nearest ancestor camera, keeping the scanner's orientation. Rays then
+    /// line up with the middle of the view, with no parallax from the scanner's offset.
+    CameraPosition,
+}
+
+/// Chooses which cloud each scanned point is stored in.
+#[derive(Clone, Debug, Default)]
+pub enum ScanRoute {
+    /// Every point goes to `Scanner::point_cloud`.
+    #[default]
+    Single,
+    /// Points below each height, in world space, go to that band's cloud. Bands are checked in
+    /// order, and points above all of them go to `Scanner::point_cloud`.
+    Height(Vec<(f32, Entity)>),
+    /// Points on these dynamic colliders go to the mapped cloud. Points on other geometry go to
+    /// `Scanner::point_cloud`.
+    Collider(EntityHashMap<Entity>),
+}
+
+impl ScanRoute {
+    /// The cloud for a point hit at `point`, on `collider` if it was a dynamic collider.
+    pub fn target(&self, default: Entity, point: Vec3, collider: Option<Entity>) -> Entity {
+        match self {
+            ScanRoute::Single => default,
+            ScanRoute::Height(bands) => bands.iter()
+                .find(|(height, _)| point.y < *height)
+                .map_or(default, |(_, target)| *target),
+            ScanRoute::Collider(targets) => collider
+                .and_then(|collider| targets.get(&collider).copied())
+                .unwrap_or(default),
+        }
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Scanner {
+    pub size_setting: f32,
+    pub angle_range: Vec2,
+    pub interval_range: Vec2,
+    pub progress: f32,
+    /// Upper bound on accumulated progress, limiting how many rays a single long frame can catch up on.
+    pub max_progress: f32,
+    /// Longest frame time the scanner simulates, in seconds. Longer frames, such as resuming from
+    /// the background, are treated as this long so warm-up and cooldowns don't skip ahead.
+    pub max_delta: f32,
+    /// Seconds to wait after the scanner starts before it emits its first ray, modelling spin-up.
+    pub warmup: f32,
+    pub active: bool,
+    pub trigger_mode: TriggerMode,
+    /// Stops the scanner from emitting rays without affecting the rest of the simulation.
+    pub frozen: bool,
+    /// Casts rays and draws their gizmos as usual, but doesn't store any points, for aiming.
+    pub preview: bool,
+    pub burst_trigger: bool,
+    pub burst_count: u32,
+    pub burst_interval: f32,
+    /// Seconds after a burst finishes before another can start, even with the trigger held.
+    pub burst_cooldown: f32,
+    /// Seconds left until the next burst can start.
+    pub burst_cooldown_remaining: f32,
+    #[reflect(ignore, default = "default_burst_pattern")]
+    pub burst_pattern: Box<dyn ScanPattern>,
+    pub burst_size: f32,
+    /// Caps how many burst rays are cast per frame; the rest carry over to later frames.
+    pub max_burst_rays_per_frame: Option<u32>,
+    /// Directions from the current burst step that have not been cast yet.
+    pub burst_pending: Vec<Vec3>,
+    pub origin_mode: OriginMode,
+    /// World-space `(min, max)` corners of a box that hits must fall in to be stored. Hits
+    /// outside it, such as from rays escaping the scene, are dropped.
+    pub bounds: Option<(Vec3, Vec3)>,
+    /// Radius of the disk, in the scanner's local XY plane, that ray origins are jittered within.
+    pub aperture_radius: f32,
+    /// Rays cast per pulse, spread within the beam to model its footprint on distant surfaces.
+    pub footprint_samples: u32,
+    /// Half-angle of the beam at the smallest and largest size settings, in radians.
+    pub footprint_angle_range: Vec2,
+    #[reflect(ignore, default = "default_scan_pattern")]
+    pub pattern: Box<dyn ScanPattern>,
+    pub point_cloud: Entity,
+    #[reflect(ignore)]
+    pub route: ScanRoute,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Scanner {
+            size_setting: 0.6,
+            angle_range: vec2(PI * 0.02, PI * 0.1),
+            interval_range: vec2(0.0011, 0.001),
+            progress: 0.0,
+            max_progress: 0.1,
+            max_delta: 0.1,
+            warmup: 0.0,
+            active: false,
+            trigger_mode: TriggerMode::Hold,
+            frozen: false,
+            preview: false,
+            burst_trigger: false,
+            burst_count: 0,
+            burst_interval: 0.01,
+            burst_cooldown: 0.0,
+            burst_cooldown_remaining: 0.0,
+            burst_pattern: default_burst_pattern(),
+            burst_size: 0.05,
+            max_burst_rays_per_frame: None,
+            burst_pending: Vec::new(),
+            origin_mode: OriginMode::SelfTransform,
+            bounds: None,
+            aperture_radius: 0.0,
+            footprint_samples: 1,
+            footprint_angle_range: vec2(0.0005, 0.005),
+            pattern: default_scan_pattern(),
+            point_cloud: Entity::PLACEHOLDER,
+            route: ScanRoute::Single,
+        }
+    }
+}
+
+fn default_scan_pattern() -> Box<dyn ScanPattern> {
+    Box::new(ConePattern)
+}
+
+fn default_burst_pattern() -> Box<dyn ScanPattern> {
+    Box::new(CrossBurstPattern::new(128))
+}
+
+pub const MAX_SCAN_DISTANCE: f32 = 200.;
+
+/// The point hit by a ray straight down the scanner's forward axis this frame, if any.
+#[derive(Clone, Copy, Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct LastScanHit(pub Option<Vec3>);
+
+#[derive(Clone, Copy, Debug, Default, Reflect)]
+pub struct ScanCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ScanCounts {
+    pub fn total(&self) -> u64 {
+        self.hits + self.misses
+    }
+}
+
+#[derive(Clone, Debug, Default, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ScanStats {
+    pub frame: ScanCounts,
+    pub total: ScanCounts,
+}
+
+/// The random source for scan patterns and aperture jitter. Insert one built with `from_seed` for
+/// reproducible scans.
+#[derive(Resource)]
+pub struct ScannerRng(pub StdRng);
+
+impl ScannerRng {
+    pub fn from_seed(seed: u64) -> ScannerRng {
+        ScannerRng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for ScannerRng {
+    fn default() -> Self {
+        ScannerRng(StdRng::from_entropy())
+    }
+}
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ScannerGizmos {
+    /// Emit a `gizmo_flush_workaround` line for each scanner every frame.
+    pub flush_workaround: bool,
+    /// Draw the part of each ray after it passed through transparent geometry in a different
+    /// colour. This costs an extra ray cast per ray.
+    pub show_transparent_passes: bool,
+}
+
+impl Default for ScannerGizmos {
+    fn default() -> Self {
+        ScannerGizmos {
+            flush_workaround: true,
+            show_transparent_passes: false,
+        }
+    }
+}
+
+/// HACK: the ray gizmos are sometimes not drawn unless an earlier line was emitted in the same
+/// frame. This draws a zero-length, fully transparent line so nothing visible is left behind.
+pub fn gizmo_flush_workaround(gizmos: &mut Gizmos, position: Vec3) {
+    gizmos.line(position, position, Color::NONE);
+}
+
+pub fn update_scan_input(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mut scroll_events: EventReader<MouseWheel>,
+    mut scanners: Query<&mut Scanner>,
+) {
+    let scroll = scroll_events.read()
+        .fold(0.0, |acc, event| acc + event.y * match event.unit {
+            MouseScrollUnit::Line => 0.1,
+            MouseScrollUnit::Pixel => 0.005,
+        });
+
+    for mut scanner in &mut scanners {
+        let idle = !scanner.active && scanner.burst_count == 0 && scanner.burst_pending.is_empty();
+        let active = match scanner.trigger_mode {
+            TriggerMode::Hold => mouse_input.pressed(MouseButton::Left),
+            TriggerMode::Toggle => scanner.active != mouse_input.just_pressed(MouseButton::Left),
+        };
+        let burst = mouse_input.pressed(MouseButton::Right);
+        if idle && (active || burst) && scanner.warmup > 0. {
+            scanner.progress = -scanner.warmup;
+        }
+
+        if active != scanner.active {
+            scanner.active = active;
+        }
+
+        if burst != scanner.burst_trigger {
+            scanner.burst_trigger = burst;
+        }
+
+        let size_setting = scanner.size_setting + scroll;
+        let size_setting = size_setting.clamp(0., 1.);
+        if size_setting != scanner.size_setting {
+            scanner.size_setting = size_setting;
+        }
+    }
+}
+
+pub fn scan(
+    time: Res<Time>,
+    physics_world: Res<PhysicsWorld>,
+    point_cloud_settings: Res<PointCloudSettings>,
+    mut scan_stats: ResMut<ScanStats>,
+    mut scanner_rng: ResMut<ScannerRng>,
+    scanner_gizmos: Res<ScannerGizmos>,
+    mut gizmos: Gizmos,
+    mut scanners: Query<(Entity, &mut Scanner, &GlobalTransform)>,
+    parents: Query<&Parent>,
+    cameras: Query<&GlobalTransform, With<Camera>>,
+    mut point_clouds: Query<&mut PointCloud>,
+    mut missing_targets: Local<EntityHashMap<Entity>>,
+    mut removed_scanners: RemovedComponents<Scanner>,
+) {
+    for entity in removed_scanners.read() {
+        missing_targets.remove(&entity);
+    }
+
+    let point_limit = point_cloud_settings.point_limit();
+    let mut counts = ScanCounts::default();
+    for (entity, mut scanner, transform) in &mut scanners {
+        if scanner.frozen {
+            scanner.progress = 0.;
+            continue;
+        }
+
+        let delta = time.delta_seconds().min(scanner.max_delta);
+        scanner.progress = (scanner.progress + delta).min(scanner.max_progress);
+        if scanner.progress < 0. {
+            // Still warming up.
+            continue;
+        }
+
+        if scanner_gizmos.flush_workaround {
+            gizmo_flush_workaround(&mut gizmos, transform.translation());
+        }
+
+        let rng = &mut scanner_rng.0;
+        if !point_clouds.contains(scanner.point_cloud) {
+            if missing_targets.insert(entity, scanner.point_cloud) != Some(scanner.point_cloud) {
+                warn!("Scanner {:?} targets {:?}, which has no PointCloud", entity, scanner.point_cloud);
+            }
+            continue;
+        }
+        missing_targets.remove(&entity);
+
+        let ray_transform = match scanner.origin_mode {
+            OriginMode::SelfTransform => *transform,
+            OriginMode::CameraPosition => {
+                let camera = parents.iter_ancestors(entity)
+                    .find_map(|ancestor| cameras.get(ancestor).ok());
+                match camera {
+                    Some(camera) => {
+                        let mut affine = transform.affine();
+                        affine.translation = camera.translation().into();
+                        GlobalTransform::from(affine)
+                    }
+                    None => {
+                        warn_once!("Scanner {:?} uses OriginMode::CameraPosition but has no camera ancestor", entity);
+                        *transform
+                    }
+                }
+            }
+        };
+
+        scanner.burst_cooldown_remaining = (scanner.burst_cooldown_remaining - delta).max(0.);
+        if scanner.burst_count == 0 && scanner.burst_pending.is_empty() && scanner.burst_trigger
+            && scanner.burst_cooldown_remaining <= 0. {
+            scanner.burst_count = scanner.burst_pattern.ticks().unwrap_or(1);
+        }
+        let was_bursting = scanner.burst_count > 0 || !scanner.burst_pending.is_empty();
+
+        let bounds = scanner.bounds;
+        // Hits and the dynamic collider they struck, routed to clouds once this scanner is done.
+        let mut hits: Vec<(Vec4, Option<Entity>)> = Vec::new();
+        let scan = |
+            gizmos: &mut Gizmos,
+            physics_world: &PhysicsWorld,
+            counts: &mut ScanCounts,
+            hits: &mut Vec<(Vec4, Option<Entity>)>,
+            transform: &GlobalTransform,
+            local_origin: Vec2,
+            local_dir: Vec3,
+        | {
+            let global_dir = transform.affine()
+                .transform_vector3(local_dir)
+                .normalize();
+
+            let max_dist = MAX_SCAN_DISTANCE;
+            let start = transform.transform_point(local_origin.extend(0.));
+
+            let end = if let Some((end, collider)) = physics_world.ray_cast_entity(start, start + global_dir * max_dist) {
+                counts.hits += 1;
+                if bounds.map_or(true, |(min, max)| end.cmpge(min).all() && end.cmple(max).all()) {
+                    hits.push((end.extend(DEFAULT_POINT_SIZE), collider));
+                }
+                end
+            } else {
+                counts.misses += 1;
+                start + global_dir * max_dist
+            };
+
+            let glass = scanner_gizmos.show_transparent_passes
+                .then(|| physics_world.ray_cast_transparent(start, end))
+                .flatten();
+            match glass {
+                Some(glass) => {
+                    gizmos.line(start, glass, SKY_BLUE);
+                    gizmos.line(glass, end, ORANGE);
+                }
+                None => gizmos.line(start, end, SKY_BLUE),
+            }
+        };
+
+        let footprint_samples = scanner.footprint_samples;
+        let footprint_angle = scanner.footprint_angle_range.x.lerp(scanner.footprint_angle_range.y, scanner.size_setting);
+
+        let mut burst_budget = scanner.max_burst_rays_per_frame.unwrap_or(u32::MAX).max(1) as usize;
+        loop {
+            if scanner.burst_pending.is_empty() {
+                if scanner.burst_count == 0 || scanner.progress < scanner.burst_interval {
+                    break;
+                }
+                scanner.progress -= scanner.burst_interval;
+                scanner.burst_count -= 1;
+
+                let angle = scanner.angle_range.x.lerp(scanner.angle_range.y, scanner.size_setting);
+                let mut ctx = ScanContext {
+                    angle,
+                    rng: &mut *rng,
+                };
+                let directions = scanner.burst_pattern.directions(&mut ctx);
+                scanner.burst_pending = directions;
+            }
+
+            if burst_budget == 0 {
+                break;
+            }
+            let count = burst_budget.min(scanner.burst_pending.len());
+            burst_budget -= count;
+            let directions = scanner.burst_pending.drain(..count).collect::<Vec<_>>();
+            for local_dir in directions {
+                let local_origin = patterns::disk_point(rng, scanner.aperture_radius);
+                for local_dir in patterns::footprint_directions(rng, local_dir, footprint_angle, footprint_samples) {
+                    scan(&mut gizmos, &physics_world, &mut counts, &mut hits, &ray_transform, local_origin, local_dir);
+                }
+            }
+        }
+
+        let bursting = scanner.burst_count > 0 || !scanner.burst_pending.is_empty();
+        if !bursting && was_bursting {
+            scanner.burst_cooldown_remaining = scanner.burst_cooldown;
+        }
+
+        if !bursting && scanner.active {
+            let interval = scanner.interval_range.x.lerp(scanner.interval_range.y, scanner.size_setting);
+            let angle = scanner.angle_range.x.lerp(scanner.angle_range.y, scanner.size_setting);
+
+            while scanner.progress > interval {
+                scanner.progress -= interval;
+
+                let mut ctx = ScanContext {
+                    angle,
+                    rng: &mut *rng,
+                };
+                for local_dir in scanner.pattern.directions(&mut ctx) {
+                    let local_origin = patterns::disk_point(rng, scanner.aperture_radius);
+                    for local_dir in patterns::footprint_directions(rng, local_dir, footprint_angle, footprint_samples) {
+                        scan(&mut gizmos, &physics_world, &mut counts, &mut hits, &ray_transform, local_origin, local_dir);
+                    }
+                }
+            }
+        } else if !bursting {
+            scanner.progress = 0.;
+        }
+
+        if hits.is_empty() || scanner.preview {
+            continue;
+        }
+        let mut routed: EntityHashMap<Vec<Vec4>> = EntityHashMap::default();
+        for (point, collider) in hits {
+            let target = scanner.route.target(scanner.point_cloud, point.truncate(), collider);
+            routed.entry(target).or_default().push(point);
+        }
+        for (target, points) in routed {
+            if let Ok(mut point_cloud) = point_clouds.get_mut(target) {
+                point_cloud.extend(points, point_limit);
+            }
+        }
+    }
+
+    scan_stats.frame = counts;
+    scan_stats.total.hits += counts.hits;
+    scan_stats.total.misses += counts.misses;
+}
+
+pub fn insert_last_scan_hit(
+    mut commands: Commands,
+    scanners: Query<Entity, (With<Scanner>, Without<LastScanHit>)>,
+) {
+    for entity in &scanners {
+        commands.entity(entity).insert(LastScanHit::default());
+    }
+}
+
+pub fn update_last_scan_hit(
+    physics_world: Res<PhysicsWorld>,
+    mut scanners: Query<(&GlobalTransform, &mut LastScanHit), With<Scanner>>,
+) {
+    for (transform, mut last_hit) in &mut scanners {
+        let start = transform.translation();
+        let end = start + transform.forward() * MAX_SCAN_DISTANCE;
+        let hit = physics_world.ray_cast(start, end);
+        if last_hit.0 != hit {
+            last_hit.0 = hit;
+        }
+    }
+}
+
+pub struct ScannerPlugin;
+
+impl Plugin for ScannerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<ScanStats>()
+            .register_type::<ScannerGizmos>()
+            .register_type::<LastScanHit>()
+            .init_resource::<ScanStats>()
+            .init_resource::<ScannerRng>()
+            .init_resource::<ScannerGizmos>()
+            .add_systems(Update, (
+                (
+                    update_scan_input,
+                    scan,
+                ).chain(),
+                (
+                    insert_last_scan_hit,
+                    update_last_scan_hit,
+                ).chain(),
+            ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::gizmos::GizmoPlugin;
+    use bevy::math::vec3;
+    use bevy::render::render_resource::Shader;
+    use bevy::time::TimeUpdateStrategy;
+    use super::*;
+
+    /// A headless app with a scanner 5 units in front of a wall, returning the scanner's cloud.
+    fn scan_app(seed: u64, scanner: Scanner) -> (App, Entity) {
+        let wall = PhysicsWorld::from_triangles(
+            vec![vec3(-50., -50., 0.), vec3(50., -50., 0.), vec3(50., 50., 0.), vec3(-50., 50., 0.)],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+
+        let mut app = App::new();
+        app
+            .add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Shader>()
+            .add_plugins((GizmoPlugin, ScannerPlugin))
+            .init_resource::<ButtonInput<MouseButton>>()
+            .add_event::<MouseWheel>()
+            .init_resource::<PointCloudSettings>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(20)))
+            .insert_resource(wall)
+            .insert_resource(ScannerRng::from_seed(seed));
+
+        let point_cloud = app.world_mut().spawn(PointCloud::default()).id();
+        app.world_mut().spawn((
+            Scanner { point_cloud, ..scanner },
+            GlobalTransform::from_translation(Vec3::Z * 5.),
+        ));
+        app.world_mut().resource_mut::<ButtonInput<MouseButton>>().press(MouseButton::Left);
+        (app, point_cloud)
+    }
+
+    fn points(app: &App, point_cloud: Entity) -> Vec<Vec4> {
+        app.world().get::<PointCloud>(point_cloud).unwrap().points.to_vec()
+    }
+
+    #[test]
+    fn seeded_scans_are_reproducible() {
+        let run = || {
+            let (mut app, point_cloud) = scan_app(7, Scanner {
+                aperture_radius: 0.01,
+                footprint_samples: 3,
+                ..default()
+            });
+            for _ in 0..10 {
+                app.update();
+            }
+            points(&app, point_cloud)
+        };
+
+        let first = run();
+        assert!(!first.is_empty());
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn no_points_are_emitted_during_warmup() {
+        let (mut app, point_cloud) = scan_app(1, Scanner {
+            warmup: 0.5,
+            ..default()
+        });
+
+        // 0.4s of 20ms frames, short of the warmup.
+        for _ in 0..20 {
+            app.update();
+        }
+        assert!(points(&app, point_cloud).is_empty());
+
+        for _ in 0..10 {
+            app.update();
+        }
+        assert!(!points(&app, point_cloud).is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+use std::f32::consts::PI;
+
+use bevy::math::{vec2, vec3};
+use bevy::prelude::*;
+use rand::{Rng, RngCore};
+
+pub struct ScanContext<'a> {
+    /// Cone angle chosen by the scanner's size setting.
+    pub angle: f32,
+    pub rng: &'a mut dyn RngCore,
+}
+
+/// Produces the local ray directions a scanner casts each tick.
+pub trait ScanPattern: Send + Sync + 'static {
+    fn directions(&mut self, ctx: &mut ScanContext) -> Vec<Vec3>;
+
+    /// The number of ticks before the pattern repeats, if it is finite. Bursts run for this long.
+    fn ticks(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Random rays within a cone whose angle follows the scanner's size setting.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConePattern;
+
+impl ScanPattern for ConePattern {
+    fn directions(&mut self, ctx: &mut ScanContext) -> Vec<Vec3> {
+        vec![cone_sample_direction(&mut *ctx.rng, ctx.angle)]
+    }
+}
+
+/// `samples` rays spread evenly across a fan in the local XZ plane each tick, like a line
+/// scanner.
+#[derive(Clone, Copy, Debug)]
+pub struct LineFanPattern {
+    pub samples: u32,
+    pub fan_angle: f32,
+}
+
+impl LineFanPattern {
+    pub fn new(samples: u32, fan_angle: f32) -> LineFanPattern {
+        LineFanPattern {
+            samples,
+            fan_angle,
+        }
+    }
+}
+
+impl ScanPattern for LineFanPattern {
+    fn directions(&mut self, _ctx: &mut ScanContext) -> Vec<Vec3> {
+        line_fan_directions(self.samples, self.fan_angle).collect()
+    }
+}
+
+/// Lines sweeping outwards along both axes, one line per tick.
+#[derive(Clone, Copy, Debug)]
+pub struct CrossBurstPattern {
+    pub lines: u32,
+    pub step: u32,
+}
+
+impl CrossBurstPattern {
+    pub fn new(lines: u32) -> CrossBurstPattern {
+        CrossBurstPattern {
+            lines,
+            step: 0,
+        }
+    }
+}
+
+impl ScanPattern for CrossBurstPattern {
+    fn directions(&mut self, _ctx: &mut ScanContext) -> Vec<Vec3> {
+        if self.step == 0 {
+            self.step = cross_burst_steps(self.lines);
+        }
+        self.step -= 1;
+        cross_burst_line(self.lines, self.step).collect()
+    }
+
+    fn ticks(&self) -> Option<u32> {
+        Some(cross_burst_steps(self.lines))
+    }
+}
+
+/// A random direction within a cone of half-angle `angle` around -Z.
+///
+/// The azimuth is uniform, and the polar angle is `sqrt(u) * angle` for uniform `u`, which
+/// spreads samples evenly over the cone's cross-section rather than bunching them at the centre.
+pub fn cone_sample_direction(rng: &mut (impl Rng + ?Sized), angle: f32) -> Vec3 {
+    let p = rng.gen_range(0.0..(2.0 * PI));
+    let r = rng.gen_range(0.0..1.0f32).sqrt() * angle;
+    let (sp, cp) = p.sin_cos();
+    let (sr, cr) = r.sin_cos();
+    vec3(sr * cp, sr * sp, -cr)
+}
+
+/// `samples` directions jittered within a cone of half-angle `angle` around `direction`. A single
+/// sample is `direction` itself.
+pub fn footprint_directions(rng: &mut impl Rng, direction: Vec3, angle: f32, samples: u32) -> Vec<Vec3> {
+    if samples <= 1 || angle <= 0. {
+        return vec![direction];
+    }
+
+    let rotation = Quat::from_rotation_arc(Vec3::NEG_Z, direction.normalize());
+    (0..samples)
+        .map(|_| rotation * cone_sample_direction(rng, angle))
+        .collect()
+}
+
+/// A point uniformly distributed within a disk of `radius` around the origin.
+pub fn disk_point(rng: &mut impl Rng, radius: f32) -> Vec2 {
+    if radius <= 0. {
+        return Vec2::ZERO;
+    }
+
+    let p = rng.gen_range(0.0..(2.0 * PI));
+    let r = rng.gen_range(0.0..1.0f32).sqrt() * radius;
+    Vec2::from_angle(p) * r
+}
+
+pub fn cone_directions<R: Rng>(rng: &mut R, angle: f32, n: usize) -> impl Iterator<Item = Vec3> + '_ {
+    (0..n).map(move |_| cone_sample_direction(rng, angle))
+}
+
+/// The offset of ray `index` of burst step `step`, on the plane one unit down -Z.
+///
+/// Steps cycle through four sides: the low two bits pick the side, and the remaining bits how far
+/// out from the centre the line is, up to 0.5. Each line spans -0.5..=0.5 along the other axis,
+/// or is a single ray through its middle.
+pub fn cross_burst_offset(lines: u32, step: u32, index: u32) -> Vec2 {
+    let axis = step & 3;
+    let major_offset = ((step >> 2) as f32) / (lines as f32) * 0.5;
+    let minor_offset = if lines > 1 {
+        (index as f32) / (lines as f32 - 1.) - 0.5
+    } else {
+        0.
+    };
+    match axis {
+        0 => vec2(major_offset, minor_offset),
+        1 => vec2(minor_offset, major_offset),
+        2 => vec2(-major_offset, -minor_offset),
+        _ => vec2(-minor_offset, -major_offset),
+    }
+}
+
+/// The ray directions of burst step `step`.
+pub fn cross_burst_line(lines: u32, step: u32) -> impl Iterator<Item = Vec3> {
+    (0..lines).map(move |index| cross_burst_offset(lines, step, index).extend(-1.).normalize())
+}
+
+pub fn cross_burst_steps(lines: u32) -> u32 {
+    lines << 2
+}
+
+pub fn cross_burst_directions(lines: u32) -> impl Iterator<Item = Vec3> {
+    (0..cross_burst_steps(lines))
+        .rev()
+        .flat_map(move |step| cross_burst_line(lines, step))
+}
+
+pub fn line_fan_direction(samples: u32, fan_angle: f32, index: u32) -> Vec3 {
+    let t = if samples > 1 {
+        (index as f32) / (samples as f32 - 1.) - 0.5
+    } else {
+        0.
+    };
+    let (s, c) = (t * fan_angle).sin_cos();
+    vec3(s, 0., -c)
+}
+
+pub fn line_fan_directions(samples: u32, fan_angle: f32) -> impl Iterator<Item = Vec3> {
+    (0..samples).map(move |index| line_fan_direction(samples, fan_angle, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    #[test]
+    fn cone_samples_lie_within_the_cone() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for angle in [0., 0.1, 0.5, 1.2] {
+            for _ in 0..1000 {
+                let direction = cone_sample_direction(&mut rng, angle);
+                assert!(direction.is_normalized(), "{direction}");
+                assert!(direction.angle_between(Vec3::NEG_Z) <= angle + 1e-4, "{direction} outside {angle}");
+            }
+        }
+    }
+
+    #[test]
+    fn cross_burst_lines_are_symmetric() {
+        for lines in [2, 3, 6, 7] {
+            for step in 0..cross_burst_steps(lines) {
+                for index in 0..lines {
+                    let offset = cross_burst_offset(lines, step, index);
+                    // Each line is symmetric about its middle...
+                    let mirror = cross_burst_offset(lines, step, lines - 1 - index);
+                    let (major, minor) = if step & 1 == 0 { (Vec2::X, Vec2::Y) } else { (Vec2::Y, Vec2::X) };
+                    assert!((offset.dot(minor) + mirror.dot(minor)).abs() < 1e-5);
+                    assert!((offset.dot(major) - mirror.dot(major)).abs() < 1e-5);
+                    // ...and opposite sides mirror each other through the centre.
+                    assert!((offset + cross_burst_offset(lines, step ^ 2, index)).abs_diff_eq(Vec2::ZERO, 1e-5));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_cross_bursts_include_the_centre_line() {
+        for lines in [1, 3, 5, 9] {
+            let middle = lines / 2;
+            for step in 0..4 {
+                assert_eq!(cross_burst_offset(lines, step, middle), Vec2::ZERO, "{lines} lines, step {step}");
+            }
+            assert!(cross_burst_directions(lines).any(|direction| direction == Vec3::NEG_Z));
+        }
+    }
+}
@@ -1,30 +1,166 @@
 use std::ops::Range;
 
+use bevy::asset::UntypedAssetId;
 use bevy::core_pipeline::core_3d::graph::{Core3d, Node3d};
 use bevy::core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state;
 use bevy::ecs::query::QueryItem;
 use bevy::pbr::MeshPipelineViewLayoutKey;
 use bevy::prelude::*;
-use bevy::render::{Render, RenderApp, RenderSet};
+use bevy::render::{Extract, Render, RenderApp, RenderSet};
 use bevy::render::camera::ExtractedCamera;
-use bevy::render::render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner};
+use bevy::render::diagnostic::RecordDiagnostics;
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::render::render_graph::{InternedRenderLabel, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner};
 use bevy::render::render_phase::{BinnedPhaseItem, CachedRenderPipelinePhaseItem, DrawFunctionId, DrawFunctions, PhaseItem, PhaseItemExtraIndex, ViewBinnedRenderPhases};
 use bevy::render::render_resource::{BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation, BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, Extent3d, FragmentState, MultisampleState, PipelineCache, PrimitiveState, RenderPassDescriptor, RenderPipelineDescriptor, ShaderStages, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages};
-use bevy::render::render_resource::binding_types::texture_2d_multisampled;
+use bevy::render::render_resource::binding_types::{texture_2d, texture_2d_multisampled};
 use bevy::render::renderer::{RenderContext, RenderDevice};
 use bevy::render::texture::{ColorAttachment, TextureCache};
 use bevy::render::view::{ExtractedView, ViewTarget};
 
+/// How the resolved transparency is blended over the view target.
+///
+/// The point pass accumulates `(rgb * a * w, a * w)` additively into the colour texture and
+/// multiplies the revealage texture by `1 - a`. The blit resolves the average colour
+/// `rgb / a` and the coverage `1 - revealage`; custom point materials must write the same
+/// outputs, via `calculate_fragment_output`, to composite correctly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum OrderIndependentTransparencyComposite {
+    /// Writes `(average, revealage)` and blends with `OneMinusSrcAlpha`/`SrcAlpha`.
+    #[default]
+    Revealage,
+    /// Writes `(average * coverage, coverage)` and blends with `One`/`OneMinusSrcAlpha`.
+    Premultiplied,
+    /// Writes `(average * coverage, coverage)` and adds it to the target, which is useful for
+    /// glowing points over a dark background.
+    Additive,
+}
+
+impl OrderIndependentTransparencyComposite {
+    pub fn blend_state(self) -> BlendState {
+        let (src_factor, dst_factor) = match self {
+            OrderIndependentTransparencyComposite::Revealage => (BlendFactor::OneMinusSrcAlpha, BlendFactor::SrcAlpha),
+            OrderIndependentTransparencyComposite::Premultiplied => (BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+            OrderIndependentTransparencyComposite::Additive => (BlendFactor::One, BlendFactor::One),
+        };
+        let blend = BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        };
+        BlendState {
+            color: blend,
+            alpha: blend,
+        }
+    }
+
+    fn premultiplied(self) -> bool {
+        self != OrderIndependentTransparencyComposite::Revealage
+    }
+}
+
+/// How overlapping point fragments are blended before the composite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum TransparencyMode {
+    /// Weighted blended order-independent transparency. Fast, but overlapping colours are only
+    /// approximately ordered.
+    #[default]
+    WeightedBlended,
+    /// Points are drawn back to front and blended with standard premultiplied alpha. Exact within
+    /// a cloud, but each cloud's draw order is re-sorted on the CPU whenever it or the camera
+    /// moves, and clouds can't share point buffer space, so it suits small clouds. Separate
+    /// clouds are not sorted against each other.
+    Sorted,
+}
+
+/// How the blit encodes the colour it writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum OrderIndependentTransparencyEncoding {
+    /// Linear colour. sRGB targets, such as non-HDR windows, encode it on write, and HDR targets
+    /// store it as is.
+    #[default]
+    Linear,
+    /// Encodes to sRGB in the shader, for targets with a plain unorm format that don't.
+    Srgb,
+}
+
+/// Where `OrderIndependentCopyPass` composites points in the `Core3d` graph.
+///
+/// On HDR cameras the view target holds scene-referred colour until tonemapping, so points
+/// composited before it are tonemapped with the scene and can look darker or desaturated.
+/// Composited after it, they show exactly the colours their material computed. Non-HDR
+/// cameras tonemap in each pass's shader, so the stage makes no difference to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Resource)]
+pub enum OrderIndependentTransparencyStage {
+    #[default]
+    BeforeTonemapping,
+    /// Multisampled accumulation is resolved in the blit, since the view target has already
+    /// been resolved by then.
+    AfterTonemapping,
+}
+
+impl OrderIndependentTransparencyStage {
+    /// The nodes the pass runs after and before at this stage.
+    pub fn graph_edges(self) -> (InternedRenderLabel, InternedRenderLabel) {
+        match self {
+            OrderIndependentTransparencyStage::BeforeTonemapping => (Node3d::MainTransparentPass.intern(), Node3d::EndMainPass.intern()),
+            OrderIndependentTransparencyStage::AfterTonemapping => (Node3d::Tonemapping.intern(), Node3d::EndMainPassPostProcessing.intern()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Resource, ExtractResource, Reflect)]
+#[reflect(Resource)]
+pub struct OrderIndependentTransparencySettings {
+    /// Initial value of the accumulated, weighted colour. The blit divides rgb by a, so keep a
+    /// non-zero if rgb is.
+    pub colour_clear: LinearRgba,
+    /// Initial revealage: the fraction of the background that shows through with no points
+    /// drawn. Only the red channel is stored.
+    pub alpha_clear: LinearRgba,
+    pub composite: OrderIndependentTransparencyComposite,
+    pub encoding: OrderIndependentTransparencyEncoding,
+    pub mode: TransparencyMode,
+}
+
+impl Default for OrderIndependentTransparencySettings {
+    fn default() -> Self {
+        OrderIndependentTransparencySettings {
+            colour_clear: LinearRgba::NONE,
+            alpha_clear: LinearRgba::WHITE,
+            composite: OrderIndependentTransparencyComposite::Revealage,
+            encoding: OrderIndependentTransparencyEncoding::Linear,
+            mode: TransparencyMode::WeightedBlended,
+        }
+    }
+}
+
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct OrderIndependentTransparencyPipelineKey {
     msaa_samples: u32,
     view_key: MeshPipelineViewLayoutKey,
+    target_format: TextureFormat,
+    composite: OrderIndependentTransparencyComposite,
+    encoding: OrderIndependentTransparencyEncoding,
+    /// Average the accumulation samples and write to the single-sampled target.
+    resolve_samples: bool,
 }
 
 #[derive(Resource)]
 pub struct OrderIndependentTransparencyPipeline {
     shader: Handle<Shader>,
     layout: BindGroupLayout,
+    multisampled_layout: BindGroupLayout,
+}
+
+impl OrderIndependentTransparencyPipeline {
+    pub fn layout(&self, multisampled: bool) -> &BindGroupLayout {
+        if multisampled {
+            &self.multisampled_layout
+        } else {
+            &self.layout
+        }
+    }
 }
 
 impl FromWorld for OrderIndependentTransparencyPipeline {
@@ -34,6 +170,16 @@ impl FromWorld for OrderIndependentTransparencyPipeline {
         let render_device = world.resource::<RenderDevice>();
         let layout = render_device.create_bind_group_layout(
             "order_independent_transparency_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+            ),
+        );
+        let multisampled_layout = render_device.create_bind_group_layout(
+            "order_independent_transparency_multisampled_layout",
             &BindGroupLayoutEntries::sequential(
                 ShaderStages::VERTEX_FRAGMENT,
                 (
@@ -45,6 +191,7 @@ impl FromWorld for OrderIndependentTransparencyPipeline {
         OrderIndependentTransparencyPipeline {
             shader,
             layout,
+            multisampled_layout,
         }
     }
 }
@@ -56,18 +203,23 @@ impl SpecializedRenderPipeline for OrderIndependentTransparencyPipeline {
         &self,
         key: Self::Key,
     ) -> RenderPipelineDescriptor {
-        let layout = vec![self.layout.clone()];
+        let multisampled = key.msaa_samples > 1;
+        let layout = vec![self.layout(multisampled).clone()];
         let mut shader_defs = vec![];
 
-        if key.msaa_samples > 1 {
+        if multisampled {
             shader_defs.push("MULTISAMPLED".into());
         }
+        if key.composite.premultiplied() {
+            shader_defs.push("PREMULTIPLIED".into());
+        }
+        if key.resolve_samples {
+            shader_defs.push("RESOLVE_SAMPLES".into());
+        }
+        if key.encoding == OrderIndependentTransparencyEncoding::Srgb {
+            shader_defs.push("SRGB_OUTPUT".into());
+        }
 
-        let blend = BlendComponent {
-            src_factor: BlendFactor::OneMinusSrcAlpha,
-            dst_factor: BlendFactor::SrcAlpha,
-            operation: BlendOperation::Add,
-        };
         RenderPipelineDescriptor {
             vertex: fullscreen_shader_vertex_state(),
             fragment: Some(FragmentState {
@@ -75,11 +227,8 @@ impl SpecializedRenderPipeline for OrderIndependentTransparencyPipeline {
                 shader_defs,
                 entry_point: "fs_main".into(),
                 targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::Rgba8UnormSrgb,
-                    blend: Some(BlendState {
-                        color: blend,
-                        alpha: blend,
-                    }),
+                    format: key.target_format,
+                    blend: Some(key.composite.blend_state()),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
@@ -87,7 +236,7 @@ impl SpecializedRenderPipeline for OrderIndependentTransparencyPipeline {
             primitive: PrimitiveState::default(),
             depth_stencil: None,
             multisample: MultisampleState {
-                count: key.msaa_samples,
+                count: if key.resolve_samples { 1 } else { key.msaa_samples },
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -106,9 +255,11 @@ pub fn prepare_order_independent_transparency_pipeline(
     mut pipelines: ResMut<SpecializedRenderPipelines<OrderIndependentTransparencyPipeline>>,
     pipeline: Res<OrderIndependentTransparencyPipeline>,
     msaa: Res<Msaa>,
-    views: Query<Entity, With<ExtractedView>>,
+    settings: Res<OrderIndependentTransparencySettings>,
+    stage: Res<OrderIndependentTransparencyStage>,
+    views: Query<(Entity, &ViewTarget), With<ExtractedView>>,
 ) {
-    for entity in &views {
+    for (entity, target) in &views {
         let view_key = if msaa.samples() > 1 {
             MeshPipelineViewLayoutKey::MULTISAMPLED
         } else {
@@ -120,6 +271,10 @@ pub fn prepare_order_independent_transparency_pipeline(
             OrderIndependentTransparencyPipelineKey {
                 msaa_samples: msaa.samples(),
                 view_key,
+                target_format: target.main_texture_format(),
+                composite: settings.composite,
+                encoding: settings.encoding,
+                resolve_samples: *stage == OrderIndependentTransparencyStage::AfterTonemapping && msaa.samples() > 1,
             },
         );
 
@@ -140,13 +295,21 @@ pub fn prepare_transparent_accumulation_texture(
     mut texture_cache: ResMut<TextureCache>,
     msaa: Res<Msaa>,
     render_device: Res<RenderDevice>,
+    settings: Res<OrderIndependentTransparencySettings>,
     views: Query<(Entity, &ExtractedCamera)>,
 ) {
     for (entity, camera) in &views {
         let Some(physical_target_size) = camera.physical_target_size else {
             continue;
         };
+        // A minimised window has a zero-sized target, which can't back a texture. Without the
+        // component the transparency nodes skip the view.
+        if physical_target_size.x == 0 || physical_target_size.y == 0 {
+            continue;
+        }
 
+        // The texture cache matches on the whole descriptor, so a resize gets new textures
+        // here and the old ones are dropped once unused.
         let size = Extent3d {
             depth_or_array_layers: 1,
             width: physical_target_size.x,
@@ -184,8 +347,8 @@ pub fn prepare_transparent_accumulation_texture(
         };
 
         commands.entity(entity).insert(TransparentAccumulationTexture {
-            color_attachment: ColorAttachment::new(colour_texture, None, Some(LinearRgba::NONE)),
-            alpha_attachment: ColorAttachment::new(alpha_texture, None, Some(LinearRgba::WHITE)),
+            color_attachment: ColorAttachment::new(colour_texture, None, Some(settings.colour_clear)),
+            alpha_attachment: ColorAttachment::new(alpha_texture, None, Some(settings.alpha_clear)),
         });
     }
 }
@@ -194,8 +357,29 @@ pub fn prepare_transparent_accumulation_texture(
 pub struct OrderIndependentTransparent3dBinKey {
     pub pipeline: CachedRenderPipelineId,
     pub draw_function: DrawFunctionId,
+    /// The material drawn. Items sharing a pipeline are only batched if they share bind groups too.
+    pub asset_id: UntypedAssetId,
+    /// The point buffer chunk the item's points live in. Each batch binds a single chunk.
+    pub chunk: u32,
 }
 
+/// The phase drawn into the order-independent transparency accumulation textures.
+///
+/// Point clouds queue into it, but any drawable can too:
+///
+/// - Add a draw function with `DrawFunctions<OrderIndependentTransparent3d>::write().add(..)`
+///   in the render app, or `app.add_render_command::<OrderIndependentTransparent3d, C>()`.
+/// - Specialize a pipeline whose fragment stage writes the two accumulation targets the way
+///   `calculate_fragment_output` in `point_cloud.wgsl` does: `Rgba16Float` colour blended with
+///   `One`/`One`, and `R16Float` revealage blended with `Zero`/`OneMinusSrcAlpha`. There is no
+///   depth attachment.
+/// - In `RenderSet::QueueMeshes`, add items to each view's phase in
+///   `ViewBinnedRenderPhases<OrderIndependentTransparent3d>`. `OrderIndependentTransparencyPlugin`
+///   creates a phase for every active 3D camera.
+///
+/// Batching goes through the point cloud pipeline, which gives items that aren't point clouds an
+/// empty placeholder draw. Queue them as unbatchable (`add(key, entity, false)`) unless their
+/// draw function handles a whole batch range itself.
 pub struct OrderIndependentTransparent3d {
     pub key: OrderIndependentTransparent3dBinKey,
     pub entity: Entity,
@@ -260,9 +444,30 @@ impl CachedRenderPipelinePhaseItem for OrderIndependentTransparent3d {
     }
 }
 
+/// Creates a phase for each active 3D camera.
+pub fn extract_camera_phases(
+    mut transparent_phases: ResMut<ViewBinnedRenderPhases<OrderIndependentTransparent3d>>,
+    cameras: Extract<Query<(Entity, &Camera), With<Camera3d>>>,
+) {
+    for (entity, camera) in &cameras {
+        if !camera.is_active {
+            continue;
+        }
+
+        transparent_phases.insert_or_clear(entity);
+    }
+
+    transparent_phases.retain(|e, _| cameras.contains(*e));
+}
+
+/// The node that draws the OIT phase and composites it onto the view target.
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 pub struct OrderIndependentCopyPass;
 
+/// Diagnostic span names, recorded when `RenderDiagnosticsPlugin` is added.
+pub const OIT_TRANSPARENT_PASS_SPAN: &str = "oit_transparent_pass_3d";
+pub const OIT_COPY_PASS_SPAN: &str = "oit_copy_pass";
+
 #[derive(Default)]
 pub struct OrderIndependentCopyNode;
 
@@ -295,6 +500,7 @@ impl ViewNode for OrderIndependentCopyNode {
 
         if !transparent_phase.is_empty() {
             let _oit_transparent_pass_3d_span = info_span!("oit_transparent_pass_3d").entered();
+            let diagnostics = render_context.diagnostic_recorder();
 
             {
                 let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
@@ -308,31 +514,44 @@ impl ViewNode for OrderIndependentCopyNode {
                     occlusion_query_set: None,
                 });
 
+                let pass_span = diagnostics.pass_span(&mut render_pass, OIT_TRANSPARENT_PASS_SPAN);
+
                 if let Some(viewport) = camera.viewport.as_ref() {
                     render_pass.set_camera_viewport(viewport);
                 }
 
                 transparent_phase.render(&mut render_pass, world, view_entity);
+                pass_span.end(&mut render_pass);
             }
 
             {
+                // The accumulation textures are recreated whenever the sample count changes, so
+                // pick the layout from the textures themselves rather than the current `Msaa`.
+                let multisampled = temp_texture.color_attachment.texture.texture.sample_count() > 1;
                 let pipeline = world.resource::<OrderIndependentTransparencyPipeline>();
                 let bind_group = render_context.render_device().create_bind_group(
                     "oit_copy_bind_group",
-                    &pipeline.layout,
+                    pipeline.layout(multisampled),
                     &BindGroupEntries::sequential((
                         &temp_texture.color_attachment.texture.default_view,
                         &temp_texture.alpha_attachment.texture.default_view,
                     )),
                 );
 
+                // After tonemapping the view target is resolved, so write to it directly rather
+                // than through the multisampled texture.
+                let color_attachment = match world.resource::<OrderIndependentTransparencyStage>() {
+                    OrderIndependentTransparencyStage::BeforeTonemapping => target.get_color_attachment(),
+                    OrderIndependentTransparencyStage::AfterTonemapping => target.get_unsampled_color_attachment(),
+                };
                 let mut copy_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
                     label: Some("oit_transparent_pass_3d"),
-                    color_attachments: &[Some(target.get_color_attachment())],
+                    color_attachments: &[Some(color_attachment)],
                     depth_stencil_attachment: None,
                     timestamp_writes: None,
                     occlusion_query_set: None,
                 });
+                let pass_span = diagnostics.pass_span(&mut copy_pass, OIT_COPY_PASS_SPAN);
 
                 if let Some(viewport) = camera.viewport.as_ref() {
                     copy_pass.set_camera_viewport(viewport);
@@ -344,6 +563,7 @@ impl ViewNode for OrderIndependentCopyNode {
                     copy_pass.set_bind_group(0, &bind_group, &[]);
                     copy_pass.draw(0..3, 0..1);
                 }
+                pass_span.end(&mut copy_pass);
             }
         }
 
@@ -351,13 +571,43 @@ impl ViewNode for OrderIndependentCopyNode {
     }
 }
 
-pub struct OrderIndependentTransparencyPlugin;
+pub struct OrderIndependentTransparencyPlugin {
+    /// Whether the pass runs before or after tonemapping. When setting custom `graph_edges`,
+    /// set this to match them.
+    pub stage: OrderIndependentTransparencyStage,
+    /// The `Core3d` nodes that `OrderIndependentCopyPass` runs after and before. With `None`, no
+    /// edges are added and the app should order the pass itself with `add_render_graph_edges`.
+    pub graph_edges: Option<(InternedRenderLabel, InternedRenderLabel)>,
+}
+
+impl OrderIndependentTransparencyPlugin {
+    pub fn with_stage(stage: OrderIndependentTransparencyStage) -> Self {
+        OrderIndependentTransparencyPlugin {
+            stage,
+            graph_edges: Some(stage.graph_edges()),
+        }
+    }
+}
+
+impl Default for OrderIndependentTransparencyPlugin {
+    fn default() -> Self {
+        OrderIndependentTransparencyPlugin::with_stage(OrderIndependentTransparencyStage::BeforeTonemapping)
+    }
+}
 
 impl Plugin for OrderIndependentTransparencyPlugin {
     fn build(&self, app: &mut App) {
-        app.sub_app_mut(RenderApp)
+        app
+            .register_type::<OrderIndependentTransparencySettings>()
+            .init_resource::<OrderIndependentTransparencySettings>()
+            .add_plugins(ExtractResourcePlugin::<OrderIndependentTransparencySettings>::default());
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(self.stage)
             .init_resource::<SpecializedRenderPipelines<OrderIndependentTransparencyPipeline>>()
             .init_resource::<DrawFunctions<OrderIndependentTransparent3d>>()
+            .init_resource::<ViewBinnedRenderPhases<OrderIndependentTransparent3d>>()
+            .add_systems(ExtractSchedule, extract_camera_phases)
             .add_systems(Render, (
                 prepare_order_independent_transparency_pipeline.in_set(RenderSet::Prepare),
                 prepare_transparent_accumulation_texture.in_set(RenderSet::PrepareResources),
@@ -365,15 +615,12 @@ impl Plugin for OrderIndependentTransparencyPlugin {
             .add_render_graph_node::<ViewNodeRunner<OrderIndependentCopyNode>>(
                 Core3d,
                 OrderIndependentCopyPass,
-            )
-            .add_render_graph_edges(
-                Core3d,
-                (
-                    Node3d::MainTransparentPass,
-                    OrderIndependentCopyPass,
-                    Node3d::EndMainPass,
-                ),
             );
+        if let Some((after, before)) = self.graph_edges {
+            render_app
+                .add_render_graph_edge(Core3d, after, OrderIndependentCopyPass)
+                .add_render_graph_edge(Core3d, OrderIndependentCopyPass, before);
+        }
     }
 
     fn finish(&self, app: &mut App) {